@@ -1,39 +1,385 @@
 
+use clap::{Parser, ValueEnum};
 use rpos::thread_logln;
-use std::{io::Write};
 
 use linux_embedded_hal::I2cdev;
 use nb::block;
 
-use ads1x1x::{channel, Ads1x1x, SlaveAddr};
+use ads1x1x::{channel, ic, mode, Ads1x1x, DataRate12Bit, DataRate16Bit, FullScaleRange, SlaveAddr};
 
-use crate::msgbus::adc_raw_publisher;
+use crate::{
+    adc_spi::{McpAdc, McpVariant},
+    client_process_args,
+    filter::{parse_filter_spec, ChannelFilters, FilterSpec},
+    msgbus::adc_raw_publisher,
+};
+
+pub const ADC_CHANNEL_NUM: usize = 16;
+
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+pub enum AdcSource{
+    #[default]
+    Unknown,
+    Ads1015,
+    Ads1115,
+    Mcp3008,
+    Mcp3208,
+    JoyDev,
+    Evdev,
+    Sim,
+}
+
+impl AdcSource{
+    pub const ITER: &'static [Self] = &[
+        Self::Unknown,
+        Self::Ads1015,
+        Self::Ads1115,
+        Self::Mcp3008,
+        Self::Mcp3208,
+        Self::JoyDev,
+        Self::Evdev,
+        Self::Sim,
+    ];
+
+    // full range of the raw values published by this source
+    pub fn range(&self) -> (i16,i16){
+        match self{
+            AdcSource::Ads1015 => (-2048,2047),
+            AdcSource::Mcp3008 => (0,1023),
+            AdcSource::Mcp3208 | AdcSource::Sim => (0,4095),
+            _ => (i16::MIN,i16::MAX),
+        }
+    }
+}
 
 #[derive(Debug,Clone,Copy,Default)]
 pub struct AdcRawMsg{
-    pub value:[i16;4]
+    pub value:[i16;ADC_CHANNEL_NUM],
+    pub valid:[bool;ADC_CHANNEL_NUM],
+    pub source:AdcSource,
+    // microseconds since unix epoch
+    pub timestamp:u64,
+}
+
+impl AdcRawMsg{
+    pub fn new(source:AdcSource,value:&[i16]) -> Self{
+        let mut msg = AdcRawMsg{
+            source,
+            timestamp:now_us(),
+            ..Default::default()
+        };
+        for (index,v) in value.iter().take(ADC_CHANNEL_NUM).enumerate(){
+            msg.value[index] = *v;
+            msg.valid[index] = true;
+        }
+        msg
+    }
+
+    pub fn channel_num(&self) -> usize{
+        self.valid.iter().filter(|x| **x).count()
+    }
+}
+
+pub fn now_us() -> u64{
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as u64)
+}
+
+pub trait AdcConverter: Send {
+    fn source(&self) -> AdcSource;
+    fn channel_num(&self) -> usize;
+    fn read(&mut self, index: usize) -> Result<i16, String>;
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ChipVariant {
+    Ads1015,
+    Ads1115,
+    Mcp3008,
+    Mcp3208,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Fsr {
+    #[value(name = "6.144")]
+    V6_144,
+    #[value(name = "4.096")]
+    V4_096,
+    #[value(name = "2.048")]
+    V2_048,
+    #[value(name = "1.024")]
+    V1_024,
+    #[value(name = "0.512")]
+    V0_512,
+    #[value(name = "0.256")]
+    V0_256,
+}
+
+impl Fsr {
+    fn to_full_scale_range(self) -> FullScaleRange {
+        match self {
+            Fsr::V6_144 => FullScaleRange::Within6_144V,
+            Fsr::V4_096 => FullScaleRange::Within4_096V,
+            Fsr::V2_048 => FullScaleRange::Within2_048V,
+            Fsr::V1_024 => FullScaleRange::Within1_024V,
+            Fsr::V0_512 => FullScaleRange::Within0_512V,
+            Fsr::V0_256 => FullScaleRange::Within0_256V,
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(name="adc", about = "used for ADS1015/ADS1115 adc chips on i2c or MCP3008/MCP3208 on spi", long_about = None)]
+struct Cli {
+    #[arg(short, long, default_value = "/dev/i2c-0")]
+    bus: String,
+
+    /// spidev device of each MCP chip, repeat to sample several chips.
+    #[arg(long, default_value = "/dev/spidev0.0")]
+    spi_dev: Vec<String>,
+
+    #[arg(long, default_value_t = 1_000_000)]
+    spi_speed: u32,
+
+    /// i2c address of each chip(0x48~0x4b), repeat to sample several chips on the same bus.
+    #[arg(short, long, default_value = "0x48", value_parser = parse_address)]
+    address: Vec<u8>,
+
+    #[arg(short, long, value_enum, default_value_t = ChipVariant::Ads1015)]
+    chip: ChipVariant,
+
+    /// full scale range in volts.
+    #[arg(short, long, value_enum, default_value_t = Fsr::V4_096)]
+    fsr: Fsr,
+
+    /// data rate in samples per second.
+    #[arg(short, long, default_value_t = 3300)]
+    rate: u32,
+
+    /// sample differential pairs(A0-A1 and A2-A3 on ADS1x1x) instead of single ended channels.
+    #[arg(short, long)]
+    differential: bool,
+
+    /// filter applied to a channel before publishing, <channel>:<avg|median|lowpass|hysteresis>:<param>,
+    /// e.g. 0:median:5 or 1:lowpass:0.2. repeat to chain several filters.
+    #[arg(long, value_parser = parse_filter_spec)]
+    filter: Vec<FilterSpec>,
+}
+
+fn parse_address(s: &str) -> Result<u8, String> {
+    let addr = u8::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|e| e.to_string())?;
+    if !(0x48..=0x4b).contains(&addr) {
+        return Err(format!("{:#x} is not an ads1x1x address(0x48~0x4b)", addr));
+    }
+    Ok(addr)
+}
+
+fn slave_addr(addr: u8) -> SlaveAddr {
+    match addr {
+        0x49 => SlaveAddr::new_vdd(),
+        0x4a => SlaveAddr::new_sda(),
+        0x4b => SlaveAddr::new_scl(),
+        _ => SlaveAddr::new_gnd(),
+    }
+}
+
+fn data_rate_12bit(sps: u32) -> Option<DataRate12Bit> {
+    Some(match sps {
+        128 => DataRate12Bit::Sps128,
+        250 => DataRate12Bit::Sps250,
+        490 => DataRate12Bit::Sps490,
+        920 => DataRate12Bit::Sps920,
+        1600 => DataRate12Bit::Sps1600,
+        2400 => DataRate12Bit::Sps2400,
+        3300 => DataRate12Bit::Sps3300,
+        _ => return None,
+    })
+}
+
+fn data_rate_16bit(sps: u32) -> Option<DataRate16Bit> {
+    Some(match sps {
+        8 => DataRate16Bit::Sps8,
+        16 => DataRate16Bit::Sps16,
+        32 => DataRate16Bit::Sps32,
+        64 => DataRate16Bit::Sps64,
+        128 => DataRate16Bit::Sps128,
+        250 => DataRate16Bit::Sps250,
+        475 => DataRate16Bit::Sps475,
+        860 => DataRate16Bit::Sps860,
+        _ => return None,
+    })
+}
+
+// ADS1015 and ADS1115 are different types in ads1x1x, so keep them in one enum
+enum Ads1x1xChip {
+    Ads1015(Ads1x1x<I2cdev, ic::Ads1015, ic::Resolution12Bit, mode::OneShot>),
+    Ads1115(Ads1x1x<I2cdev, ic::Ads1115, ic::Resolution16Bit, mode::OneShot>),
 }
 
-fn adc_main(_argc: u32, _argv: *const &str) {
-    let dev = I2cdev::new("/dev/i2c-0").unwrap();
-    let address = SlaveAddr::default();
-    let mut adc = Ads1x1x::new_ads1015(dev, address);
-    adc.set_full_scale_range(ads1x1x::FullScaleRange::Within4_096V).unwrap();
-    adc.set_data_rate(ads1x1x::DataRate12Bit::Sps3300).unwrap();
+macro_rules! read_channel {
+    ($adc:expr, $index:expr, $differential:expr) => {
+        match ($differential, $index) {
+            (false, 0) => block!($adc.read(channel::SingleA0)),
+            (false, 1) => block!($adc.read(channel::SingleA1)),
+            (false, 2) => block!($adc.read(channel::SingleA2)),
+            (false, 3) => block!($adc.read(channel::SingleA3)),
+            (true, 0) => block!($adc.read(channel::DifferentialA0A1)),
+            (true, 1) => block!($adc.read(channel::DifferentialA2A3)),
+            _ => return Err(format!("no channel {}", $index)),
+        }
+    };
+}
+
+struct Ads1x1xAdc {
+    chip: Ads1x1xChip,
+    differential: bool,
+}
+
+impl Ads1x1xAdc {
+    fn new(args: &Cli, addr: u8) -> Result<Self, String> {
+        let dev = I2cdev::new(&args.bus).map_err(|e| format!("{}: {}", args.bus, e))?;
+        let fsr = args.fsr.to_full_scale_range();
+        let chip = match args.chip {
+            ChipVariant::Ads1015 => {
+                let rate = data_rate_12bit(args.rate)
+                    .ok_or(format!("ads1015 does not support {} sps", args.rate))?;
+                let mut adc = Ads1x1x::new_ads1015(dev, slave_addr(addr));
+                adc.set_full_scale_range(fsr).map_err(|e| format!("{:?}", e))?;
+                adc.set_data_rate(rate).map_err(|e| format!("{:?}", e))?;
+                Ads1x1xChip::Ads1015(adc)
+            }
+            ChipVariant::Ads1115 => {
+                let rate = data_rate_16bit(args.rate)
+                    .ok_or(format!("ads1115 does not support {} sps", args.rate))?;
+                let mut adc = Ads1x1x::new_ads1115(dev, slave_addr(addr));
+                adc.set_full_scale_range(fsr).map_err(|e| format!("{:?}", e))?;
+                adc.set_data_rate(rate).map_err(|e| format!("{:?}", e))?;
+                Ads1x1xChip::Ads1115(adc)
+            }
+            ChipVariant::Mcp3008 | ChipVariant::Mcp3208 => return Err("not an ads1x1x chip".to_string()),
+        };
+        Ok(Ads1x1xAdc {
+            chip,
+            differential: args.differential,
+        })
+    }
+}
+
+impl AdcConverter for Ads1x1xAdc {
+    fn source(&self) -> AdcSource {
+        match self.chip {
+            Ads1x1xChip::Ads1015(_) => AdcSource::Ads1015,
+            Ads1x1xChip::Ads1115(_) => AdcSource::Ads1115,
+        }
+    }
+
+    fn channel_num(&self) -> usize {
+        if self.differential {
+            2
+        } else {
+            4
+        }
+    }
+
+    fn read(&mut self, index: usize) -> Result<i16, String> {
+        let differential = self.differential;
+        match &mut self.chip {
+            Ads1x1xChip::Ads1015(adc) => read_channel!(adc, index, differential),
+            Ads1x1xChip::Ads1115(adc) => read_channel!(adc, index, differential),
+        }
+        .map_err(|e| format!("{:?}", e))
+    }
+}
+
+// reads every channel of every converter in order, then filters them
+pub struct AdcSampler {
+    converters: Vec<Box<dyn AdcConverter>>,
+    filters: ChannelFilters,
+    value: Vec<i16>,
+}
+
+impl AdcSampler {
+    pub fn new(converters: Vec<Box<dyn AdcConverter>>, filter_specs: &[FilterSpec]) -> Result<Self, String> {
+        let channel_num: usize = converters.iter().map(|c| c.channel_num()).sum();
+        if channel_num > ADC_CHANNEL_NUM {
+            return Err(format!("{} adc channels configured, at most {} supported.", channel_num, ADC_CHANNEL_NUM));
+        }
+        if let Some(spec) = filter_specs.iter().find(|f| f.channel >= channel_num) {
+            return Err(format!("filter on channel {} but only {} channels configured.", spec.channel, channel_num));
+        }
+        Ok(AdcSampler {
+            converters,
+            filters: ChannelFilters::new(channel_num, filter_specs),
+            value: vec![0; channel_num],
+        })
+    }
+
+    pub fn sample(&mut self) -> Result<AdcRawMsg, String> {
+        let mut index = 0;
+        for converter in self.converters.iter_mut() {
+            for i in 0..converter.channel_num() {
+                self.value[index] = converter.read(i)?;
+                index += 1;
+            }
+        }
+
+        self.filters.apply(&mut self.value);
+        let source = self.converters.first().map_or(AdcSource::Unknown, |c| c.source());
+        Ok(AdcRawMsg::new(source, &self.value))
+    }
+}
+
+fn create_converters(args: &Cli) -> Result<Vec<Box<dyn AdcConverter>>, String> {
+    let mut converters: Vec<Box<dyn AdcConverter>> = Vec::new();
+    match args.chip {
+        ChipVariant::Ads1015 | ChipVariant::Ads1115 => {
+            for addr in &args.address {
+                let adc = Ads1x1xAdc::new(args, *addr).map_err(|e| format!("adc at {:#x}: {}", addr, e))?;
+                converters.push(Box::new(adc));
+            }
+        }
+        ChipVariant::Mcp3008 | ChipVariant::Mcp3208 => {
+            let variant = match args.chip {
+                ChipVariant::Mcp3008 => McpVariant::Mcp3008,
+                _ => McpVariant::Mcp3208,
+            };
+            for dev_name in &args.spi_dev {
+                converters.push(Box::new(McpAdc::new(dev_name, args.spi_speed, variant, args.differential)?));
+            }
+        }
+    }
+    Ok(converters)
+}
+
+fn adc_main(argc: u32, argv: *const &str) {
+    let ret = client_process_args::<Cli>(argc, argv);
+    if ret.is_none() {
+        return;
+    }
+
+    let args = ret.unwrap();
+    let sampler = create_converters(&args).and_then(|c| AdcSampler::new(c, &args.filter));
+    let mut sampler = match sampler {
+        Ok(s) => s,
+        Err(e) => {
+            thread_logln!("failed to init adc: {}", e);
+            return;
+        }
+    };
 
     let adc_raw_tx = adc_raw_publisher();
 
     thread_logln!("adc thread started!");
 
     loop{
-        let value = [
-            block!(adc.read(channel::SingleA0)).unwrap(),
-            block!(adc.read(channel::SingleA1)).unwrap(),
-            block!(adc.read(channel::SingleA2)).unwrap(),
-            block!(adc.read(channel::SingleA3)).unwrap()
-        ];
-
-        adc_raw_tx.publish(AdcRawMsg{ value });
+        match sampler.sample() {
+            Ok(msg) => adc_raw_tx.publish(msg),
+            Err(e) => {
+                thread_logln!("failed to read adc: {}", e);
+                return;
+            }
+        }
     }
 }
 
@@ -41,3 +387,57 @@ fn adc_main(_argc: u32, _argv: *const &str) {
 fn register() {
     rpos::module::Module::register("adc", adc_main);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::parse_filter_spec;
+
+    struct MockAdc {
+        values: Vec<i16>,
+    }
+
+    impl AdcConverter for MockAdc {
+        fn source(&self) -> AdcSource {
+            AdcSource::Mcp3008
+        }
+
+        fn channel_num(&self) -> usize {
+            self.values.len()
+        }
+
+        fn read(&mut self, index: usize) -> Result<i16, String> {
+            let v = self.values[index];
+            self.values[index] += 100;
+            Ok(v)
+        }
+    }
+
+    #[test]
+    fn test_sampler_with_mock_converters() {
+        let converters: Vec<Box<dyn AdcConverter>> = vec![
+            Box::new(MockAdc { values: vec![0, 1, 2, 3] }),
+            Box::new(MockAdc { values: vec![10, 11] }),
+        ];
+        let mut sampler = AdcSampler::new(converters, &[parse_filter_spec("5:avg:2").unwrap()]).unwrap();
+
+        let msg = sampler.sample().unwrap();
+        assert_eq!(msg.source, AdcSource::Mcp3008);
+        assert_eq!(msg.channel_num(), 6);
+        assert_eq!(&msg.value[..6], &[0, 1, 2, 3, 10, 11]);
+
+        let msg = sampler.sample().unwrap();
+        assert_eq!(&msg.value[..6], &[100, 101, 102, 103, 110, 61]);
+    }
+
+    #[test]
+    fn test_sampler_invalid_config() {
+        let converters: Vec<Box<dyn AdcConverter>> = (0..3)
+            .map(|_| Box::new(MockAdc { values: vec![0; 8] }) as Box<dyn AdcConverter>)
+            .collect();
+        assert!(AdcSampler::new(converters, &[]).is_err());
+
+        let converters: Vec<Box<dyn AdcConverter>> = vec![Box::new(MockAdc { values: vec![0; 4] })];
+        assert!(AdcSampler::new(converters, &[parse_filter_spec("4:avg:2").unwrap()]).is_err());
+    }
+}
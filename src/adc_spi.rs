@@ -0,0 +1,98 @@
+use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+
+use crate::adc::{AdcConverter, AdcSource};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum McpVariant {
+    Mcp3008,
+    Mcp3208,
+}
+
+// command bytes for one conversion, see the "SPI communication" section of the datasheets.
+// in differential mode `channel` selects the pair CH(2n)+/CH(2n+1)-.
+fn mcp_command(variant: McpVariant, channel: usize, differential: bool) -> [u8; 3] {
+    let code = if differential { channel * 2 } else { channel } as u8;
+    let single = !differential as u8;
+    match variant {
+        McpVariant::Mcp3008 => [0x01, (single << 7) | (code << 4), 0x00],
+        McpVariant::Mcp3208 => [0x04 | (single << 1) | (code >> 2), (code & 0x03) << 6, 0x00],
+    }
+}
+
+fn mcp_decode(variant: McpVariant, rx: &[u8; 3]) -> i16 {
+    match variant {
+        McpVariant::Mcp3008 => (((rx[1] & 0x03) as i16) << 8) | rx[2] as i16,
+        McpVariant::Mcp3208 => (((rx[1] & 0x0f) as i16) << 8) | rx[2] as i16,
+    }
+}
+
+pub struct McpAdc {
+    spi: Spidev,
+    variant: McpVariant,
+    differential: bool,
+}
+
+impl McpAdc {
+    pub fn new(dev_name: &str, speed_hz: u32, variant: McpVariant, differential: bool) -> Result<Self, String> {
+        let mut spi = Spidev::open(dev_name).map_err(|e| format!("{}: {}", dev_name, e))?;
+        let options = SpidevOptions::new()
+            .bits_per_word(8)
+            .max_speed_hz(speed_hz)
+            .mode(SpiModeFlags::SPI_MODE_0)
+            .build();
+        spi.configure(&options).map_err(|e| format!("{}: {}", dev_name, e))?;
+        Ok(McpAdc {
+            spi,
+            variant,
+            differential,
+        })
+    }
+}
+
+impl AdcConverter for McpAdc {
+    fn source(&self) -> AdcSource {
+        match self.variant {
+            McpVariant::Mcp3008 => AdcSource::Mcp3008,
+            McpVariant::Mcp3208 => AdcSource::Mcp3208,
+        }
+    }
+
+    fn channel_num(&self) -> usize {
+        if self.differential {
+            4
+        } else {
+            8
+        }
+    }
+
+    fn read(&mut self, index: usize) -> Result<i16, String> {
+        let tx = mcp_command(self.variant, index, self.differential);
+        let mut rx = [0u8; 3];
+        let mut transfer = SpidevTransfer::read_write(&tx, &mut rx);
+        self.spi.transfer(&mut transfer).map_err(|e| e.to_string())?;
+        Ok(mcp_decode(self.variant, &rx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mcp3008_command() {
+        assert_eq!(mcp_command(McpVariant::Mcp3008, 0, false), [0x01, 0x80, 0x00]);
+        assert_eq!(mcp_command(McpVariant::Mcp3008, 7, false), [0x01, 0xf0, 0x00]);
+        // CH2+/CH3-
+        assert_eq!(mcp_command(McpVariant::Mcp3008, 1, true), [0x01, 0x20, 0x00]);
+        assert_eq!(mcp_decode(McpVariant::Mcp3008, &[0xff, 0xfe, 0xab]), 0x2ab);
+    }
+
+    #[test]
+    fn test_mcp3208_command() {
+        assert_eq!(mcp_command(McpVariant::Mcp3208, 0, false), [0x06, 0x00, 0x00]);
+        assert_eq!(mcp_command(McpVariant::Mcp3208, 5, false), [0x07, 0x40, 0x00]);
+        // CH6+/CH7-
+        assert_eq!(mcp_command(McpVariant::Mcp3208, 3, true), [0x05, 0x80, 0x00]);
+        assert_eq!(mcp_decode(McpVariant::Mcp3208, &[0xff, 0xf9, 0x87]), 0x987);
+    }
+}
@@ -1,10 +1,15 @@
-use std::{time::Duration, io::Write};
+use std::{fmt, sync::Mutex, time::{Duration, Instant}};
 
+use clap::{Parser, Subcommand};
 use rpos::thread_logln;
 
-use crate::{adc::AdcRawMsg, CALIBRATE_FILENAME};
+use crate::{adc::{AdcRawMsg, ADC_CHANNEL_NUM}, client_process_args, stick_mode::StickMode, write_file_atomic, CALIBRATE_FILENAME};
 use crate::msgbus::{adc_raw_subscriber, TopicReader};
 
+const FEEDBACK_INTERVAL: Duration = Duration::from_secs(1);
+// peak-to-peak noise at rest above this percentage of the calibrated range is reported as noisy
+const NOISY_PERCENT: i32 = 2;
+
 #[allow(dead_code)]
 pub trait EnumIter
 where
@@ -13,127 +18,454 @@ where
     const ITER: &'static [Self];
 }
 
-pub enum JoystickChannel {
-    Thrust,
-    Direction,
-    Aileron,
-    Elevator,
+// self-centering inputs get a center point, throttle-style sticks, pots and sliders stay linear
+#[derive(Default,Clone,Copy,PartialEq,Debug,serde::Serialize,serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputKind {
+    Centering,
+    #[default]
+    NonCentering,
+}
+
+#[derive(Clone,PartialEq,Debug)]
+pub struct InputSpec {
+    pub name: String,
+    pub kind: InputKind,
 }
 
-impl JoystickChannel {
-    pub const ITER: &'static [Self] = &[Self::Thrust, Self::Direction, Self::Aileron, Self::Elevator];
-    pub const STRS: &'static [&'static str] = &["Thrust", "Direction", "Aileron", "Elevator"];
+// calibrated when no --input is given
+pub const STICK_INPUTS: &[(&str, InputKind)] = &[
+    ("Thrust", InputKind::NonCentering),
+    ("Direction", InputKind::Centering),
+    ("Aileron", InputKind::Centering),
+    ("Elevator", InputKind::Centering),
+];
+
+// format: <name>:<centering|non-centering>, e.g. Pot1:non-centering
+fn parse_input_spec(s: &str) -> Result<InputSpec, String> {
+    let (name, kind) = s
+        .split_once(':')
+        .ok_or_else(|| format!("{} should be <name>:<centering|non-centering>", s))?;
+    if name.is_empty() {
+        return Err(format!("empty input name in {}", s));
+    }
+    let kind = match kind {
+        "centering" => InputKind::Centering,
+        "non-centering" => InputKind::NonCentering,
+        _ => return Err(format!("unknown input type:{}", kind)),
+    };
+    Ok(InputSpec { name: name.to_string(), kind })
 }
 
 #[derive(Default,Clone,Debug,serde::Serialize,serde::Deserialize)]
 pub struct ChannelInfo {
     pub name: String,
+    #[serde(default)]
+    pub kind: InputKind,
     pub index: u8,
     pub min: i16,
     pub max: i16,
+    #[serde(default)]
+    pub center: Option<i16>,
+    // raw values within center +- deadband are treated as center
+    #[serde(default)]
+    pub deadband: u16,
     pub rev: bool,
 }
 
-#[derive(serde::Serialize,serde::Deserialize)]
+#[derive(Default,Clone,serde::Serialize,serde::Deserialize)]
 pub struct CalibrationData{
     pub channel_infos:Vec<ChannelInfo>,
-    pub channel_indexs:Vec<u8>
+    pub channel_indexs:Vec<u8>,
+    // stick mode the sticks were named in during calibration
+    #[serde(default)]
+    pub calibrated_mode: StickMode,
+    // stick mode the mixer uses, see `mixer mode`
+    #[serde(default)]
+    pub stick_mode: StickMode,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CalibrationError {
+    Io(String),
+    Parse(String),
+    MissingChannel(String),
+    DuplicateName(String),
+    IndexOutOfRange { name: String, index: u8 },
+    DuplicateIndex { first: String, second: String, index: u8 },
+    EmptyRange { name: String, min: i16, max: i16 },
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationError::Io(e) => write!(f, "{}", e),
+            CalibrationError::Parse(e) => write!(f, "invalid toml: {}", e),
+            CalibrationError::MissingChannel(name) => write!(f, "channel {} is not calibrated", name),
+            CalibrationError::DuplicateName(name) => write!(f, "channel {} is calibrated twice", name),
+            CalibrationError::IndexOutOfRange { name, index } => {
+                write!(f, "channel {} uses adc index {}, only {} are available", name, index, ADC_CHANNEL_NUM)
+            }
+            CalibrationError::DuplicateIndex { first, second, index } => {
+                write!(f, "channel {} and {} both use adc index {}", first, second, index)
+            }
+            CalibrationError::EmptyRange { name, min, max } => {
+                write!(f, "channel {} has min {} >= max {}, was it moved during calibration?", name, min, max)
+            }
+        }
+    }
 }
 
+impl CalibrationData {
+    // inputs are looked up by name, case insensitive
+    pub fn get(&self, name: &str) -> Option<&ChannelInfo> {
+        self.channel_infos.iter().find(|x| x.name.eq_ignore_ascii_case(name))
+    }
+
+    // checks everything cal_mixout relies on, `required` are the input names the caller looks up
+    pub fn validate(&self, required: &[&str]) -> Result<(), CalibrationError> {
+        if let Some(name) = required.iter().find(|x| self.get(x).is_none()) {
+            return Err(CalibrationError::MissingChannel(name.to_string()));
+        }
+        for (i, info) in self.channel_infos.iter().enumerate() {
+            if self.channel_infos[..i].iter().any(|x| x.name.eq_ignore_ascii_case(&info.name)) {
+                return Err(CalibrationError::DuplicateName(info.name.clone()));
+            }
+            if info.index as usize >= ADC_CHANNEL_NUM {
+                return Err(CalibrationError::IndexOutOfRange { name: info.name.clone(), index: info.index });
+            }
+            if let Some(other) = self.channel_infos[..i].iter().find(|x| x.index == info.index) {
+                return Err(CalibrationError::DuplicateIndex {
+                    first: other.name.clone(),
+                    second: info.name.clone(),
+                    index: info.index,
+                });
+            }
+            if info.min >= info.max {
+                return Err(CalibrationError::EmptyRange { name: info.name.clone(), min: info.min, max: info.max });
+            }
+        }
+        Ok(())
+    }
+
+    // the previous file is kept as <filename>.bak
+    pub fn save(&self, filename: &str) -> Result<(), CalibrationError> {
+        let io_err = |e: std::io::Error| CalibrationError::Io(format!("{}: {}", filename, e));
+        if std::path::Path::new(filename).exists() {
+            std::fs::copy(filename, format!("{}.bak", filename)).map_err(io_err)?;
+        }
+        write_file_atomic(filename, &toml::to_string(self).unwrap()).map_err(io_err)
+    }
+
+    pub fn load(filename: &str, required: &[&str]) -> Result<Self, CalibrationError> {
+        let toml_str = std::fs::read_to_string(filename).map_err(|e| CalibrationError::Io(format!("{}: {}", filename, e)))?;
+        let data = toml::from_str::<CalibrationData>(&toml_str).map_err(|e| CalibrationError::Parse(e.to_string()))?;
+        data.validate(required)?;
+        Ok(data)
+    }
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
 enum CalibrateState {
     Idle,
     LowestCheck(u8),
     MinMaxCheck,
     Finish
 }
+
+// the running calibration, shared by the `calibrate` session thread and the command threads
+static CALIBRATION: Mutex<Option<Calibration>> = Mutex::new(None);
+
+#[derive(Parser)]
+#[command(name="calibrate", about = "calibrate joysticks, run without command to start", long_about = None)]
+struct Cli {
+    /// input to calibrate, <name>:<centering|non-centering>, e.g. Pot1:non-centering.
+    /// repeat for each input, defaults to the four sticks.
+    /// given inputs are merged into the saved calibration, the four sticks replace it.
+    #[arg(short, long, value_parser = parse_input_spec)]
+    input: Vec<InputSpec>,
+
+    #[command(subcommand)]
+    command: Option<CalibrateCommand>,
+}
+
+#[derive(Subcommand)]
+enum CalibrateCommand {
+    /// confirm the current step and go to the next one.
+    Next,
+    /// redo the previous step.
+    Back,
+    /// stop calibrating without saving.
+    Abort,
+    /// show the current step.
+    Status,
+    /// recalibrate a single input of the saved calibration, keeping the others.
+    Channel {
+        name: String,
+    },
+    /// toggle reversal of an input in the saved calibration.
+    Rev {
+        name: String,
+    },
+}
+
 struct Calibration {
+    inputs: Vec<InputSpec>,
     state: CalibrateState,
     data:CalibrationData,
-    rx: TopicReader<AdcRawMsg>,
+    sample: CalSample,
+    center: Option<AdcRawMsg>,
+    // measured at rest in the center step
+    noise: [NoiseStats; ADC_CHANNEL_NUM],
+    // detected in LowestCheck, one per confirmed channel
+    revs: Vec<bool>,
+    // the saved calibration a single channel or a list of inputs is merged into
+    base: Option<CalibrationData>,
+    // `calibrate channel`: the adc index is kept, LowestCheck is skipped
+    single: bool,
 }
 
 impl Calibration {
-    fn new() -> Self {
+    fn new(inputs: Vec<InputSpec>) -> Self {
         Calibration {
+            inputs,
             state: CalibrateState::Idle,
-            rx: adc_raw_subscriber(),
-            data: CalibrationData{
-                channel_infos: Vec::new(),
-                channel_indexs:Vec::new()
-            }
+            sample: CalSample::new(),
+            center: None,
+            noise: [NoiseStats::default(); ADC_CHANNEL_NUM],
+            revs: Vec::new(),
+            base: None,
+            single: false,
+            data: CalibrationData::default(),
         }
     }
 
-    fn do_sample(&mut self,prewait_seconds:u32,sample_seconds:u32) -> CalSample {
-        let mut sample = CalSample::new(self.rx.clone());
-        std::thread::sleep(Duration::from_secs(prewait_seconds as u64));
-        sample.sample_in_seconds(sample_seconds);
-        sample
+    // keeps the adc index and rev of the saved input, only center and min/max are measured again
+    fn new_single(base: CalibrationData, name: &str) -> Result<Self, CalibrationError> {
+        let info = base.get(name).ok_or_else(|| CalibrationError::MissingChannel(name.to_string()))?.clone();
+        let mut cal = Calibration::new(vec![InputSpec { name: info.name, kind: info.kind }]);
+        cal.data.channel_indexs.push(info.index);
+        cal.revs.push(info.rev);
+        cal.base = Some(base);
+        cal.single = true;
+        Ok(cal)
+    }
+
+    fn prompt(&self) -> String {
+        match self.state {
+            CalibrateState::Idle => "step 0:push joysticks to center, then `calibrate next`.".to_string(),
+            CalibrateState::LowestCheck(x) => format!(
+                "step {}:push channel:{} to lowest side or leftmost side, then `calibrate next`.", x+1,
+                self.inputs[x as usize].name
+            ),
+            CalibrateState::MinMaxCheck => "last step: rotate all your joysticks to check min and max value, then `calibrate next`.".to_string(),
+            CalibrateState::Finish => "finished.".to_string(),
+        }
+    }
+
+    // live result of the samples collected in the current step
+    fn feedback(&self) -> Option<String> {
+        if self.sample.list.is_empty() {
+            return None;
+        }
+        match self.state {
+            CalibrateState::LowestCheck(_) => {
+                let chn = self.sample.find_largest_change_channel();
+                Some(format!(
+                    "detected channel:{} value:{}{}", chn, self.sample.list.last().unwrap().value[chn as usize],
+                    if self.sample.is_reversed(chn) { " reversed" } else { "" }
+                ))
+            }
+            CalibrateState::MinMaxCheck => {
+                let ranges: Vec<String> = self.data.channel_indexs.iter().enumerate().map(|(index, chn)| {
+                    format!("{}:[{},{}]", self.inputs[index].name, self.sample.get_min_of_channel(*chn), self.sample.get_max_of_channel(*chn))
+                }).collect();
+                Some(ranges.join(" "))
+            }
+            CalibrateState::Idle => {
+                let avg = self.sample.get_average();
+                let centers: Vec<String> = avg.value.iter().zip(avg.valid)
+                    .enumerate()
+                    .filter(|(_, (_, valid))| *valid)
+                    .map(|(chn, (v, _))| format!("{}:{}", chn, v))
+                    .collect();
+                Some(format!("center:[{}]", centers.join(" ")))
+            }
+            _ => None,
+        }
     }
 
-    fn do_step(&mut self) {
+    fn next(&mut self) -> Result<(), String> {
         match self.state {
             CalibrateState::Idle => {
-                thread_logln!("start calibrate joysticks!");
-                thread_logln!("step 0:push joysticks to center.");
-                std::thread::sleep(std::time::Duration::from_secs(5));
-                self.state = CalibrateState::LowestCheck(0);
+                if self.sample.list.is_empty() {
+                    return Err("no input received yet.".to_string());
+                }
+                self.center = Some(self.sample.get_average());
+                self.noise = std::array::from_fn(|i| self.sample.get_noise_of_channel(i as u8));
+                self.state = if self.single {
+                    CalibrateState::MinMaxCheck
+                } else {
+                    CalibrateState::LowestCheck(0)
+                };
             }
             CalibrateState::LowestCheck(x) => {
-                thread_logln!(
-                    "step {}:push channel:{} to lowest side or leftmost side", x+1,
-                    JoystickChannel::STRS[x as usize]
-                );
-
-                let sample = self.do_sample(2,3);
-                let chn = sample.find_largest_change_channel();
+                if self.sample.list.is_empty() {
+                    return Err("no input received yet.".to_string());
+                }
+                let chn = self.sample.find_largest_change_channel();
+                if let Some(used) = self.data.channel_indexs.iter().position(|x| *x == chn) {
+                    return Err(format!(
+                        "adc channel:{} is already used by {}, move {} only.",
+                        chn, self.inputs[used].name, self.inputs[x as usize].name
+                    ));
+                }
+                if let Some(kept) = self.kept_input_at(chn) {
+                    return Err(format!(
+                        "adc channel:{} is used by {} in {}, move {} only or calibrate {} too.",
+                        chn, kept, CALIBRATE_FILENAME, self.inputs[x as usize].name, kept
+                    ));
+                }
                 self.data.channel_indexs.push(chn);
+                self.revs.push(self.sample.is_reversed(chn));
 
                 let next_channel = x + 1;
-                if next_channel == JoystickChannel::ITER.len() as u8 {
+                if next_channel == self.inputs.len() as u8 {
                     self.state = CalibrateState::MinMaxCheck;
                 } else {
                     self.state = CalibrateState::LowestCheck(next_channel);
                 }
             }
             CalibrateState::MinMaxCheck =>{
-                thread_logln!("last step: rotate all your joysticks to check min and max value.");
-                let sample = self.do_sample(0, 10);
-                for (index,_) in JoystickChannel::ITER.iter().enumerate(){
+                if self.sample.list.is_empty() {
+                    return Err("no input received yet.".to_string());
+                }
+                for (index,input) in self.inputs.iter().enumerate(){
                     let channel_index = self.data.channel_indexs[index];
-                    let min = sample.get_min_of_channel(channel_index);
-                    let max = sample.get_max_of_channel(channel_index);
+                    let min = self.sample.get_min_of_channel(channel_index);
+                    let max = self.sample.get_max_of_channel(channel_index);
+                    let (center, deadband) = match input.kind {
+                        InputKind::Centering => (
+                            self.center.map(|c| c.value[channel_index as usize].clamp(min, max)),
+                            self.noise[channel_index as usize].suggested_deadband(),
+                        ),
+                        InputKind::NonCentering => (None, 0),
+                    };
                     let chn = ChannelInfo{
                         index: channel_index,
+                        kind: input.kind,
                         min,
                         max,
-                        rev:false,
-                        name:JoystickChannel::STRS[index].to_string(),
+                        center,
+                        deadband,
+                        rev:self.revs[index],
+                        name:input.name.clone(),
                     };
                     self.data.channel_infos.push(chn);
                 }
                 self.state = CalibrateState::Finish;
             },
-            CalibrateState::Finish =>{
-                thread_logln!("finished.");
-                for (index,channel_name) in JoystickChannel::STRS.iter().enumerate(){
-                    thread_logln!("{}:{:?}",channel_name,self.data.channel_infos[index]);
+            CalibrateState::Finish => {}
+        }
+        self.sample = CalSample::new();
+        Ok(())
+    }
+
+    fn back(&mut self) {
+        self.state = match self.state {
+            CalibrateState::Idle | CalibrateState::LowestCheck(0) => {
+                self.center = None;
+                CalibrateState::Idle
+            }
+            CalibrateState::LowestCheck(x) => {
+                self.data.channel_indexs.pop();
+                self.revs.pop();
+                CalibrateState::LowestCheck(x - 1)
+            }
+            CalibrateState::MinMaxCheck if self.single => {
+                self.center = None;
+                CalibrateState::Idle
+            }
+            CalibrateState::MinMaxCheck => {
+                self.data.channel_indexs.pop();
+                self.revs.pop();
+                CalibrateState::LowestCheck(self.inputs.len() as u8 - 1)
+            }
+            CalibrateState::Finish => {
+                self.data.channel_infos.clear();
+                CalibrateState::MinMaxCheck
+            }
+        };
+        self.sample = CalSample::new();
+    }
+
+    // a saved input at adc index `chn` that this run merges into the result unchanged
+    fn kept_input_at(&self, chn: u8) -> Option<&str> {
+        let base = self.base.as_ref()?;
+        base.channel_infos
+            .iter()
+            .find(|info| info.index == chn && !self.inputs.iter().any(|x| x.name.eq_ignore_ascii_case(&info.name)))
+            .map(|info| info.name.as_str())
+    }
+
+    fn noise_report(&self) -> Vec<String> {
+        self.data.channel_infos.iter().map(|info| {
+            let noise = self.noise[info.index as usize];
+            let noisy = noise.peak_to_peak as i32 * 100 > (info.max as i32 - info.min as i32) * NOISY_PERCENT;
+            format!(
+                "{}: std-dev:{:.1} p2p:{} deadband:{}{}", info.name, noise.std_dev, noise.peak_to_peak, info.deadband,
+                if noisy { " NOISY, the pot may be worn" } else { "" }
+            )
+        }).collect()
+    }
+
+    // the data to write, calibrated inputs replace the saved ones of the same name, new ones are added
+    fn result(&self) -> CalibrationData {
+        match &self.base {
+            Some(base) => {
+                let mut merged = base.clone();
+                for info in self.data.channel_infos.iter() {
+                    match merged.channel_infos.iter_mut().find(|x| x.name.eq_ignore_ascii_case(&info.name)) {
+                        Some(old) => *old = info.clone(),
+                        None => merged.channel_infos.push(info.clone()),
+                    }
                 }
+                merged.channel_indexs = merged.channel_infos.iter().map(|x| x.index).collect();
+                merged
             }
+            None => self.data.clone(),
         }
     }
+
+    fn save(&self) -> Result<(), CalibrationError> {
+        let data = self.result();
+        let names: Vec<&str> = data.channel_infos.iter().map(|x| x.name.as_str()).collect();
+        data.validate(&names)?;
+        data.save(CALIBRATE_FILENAME)
+    }
+}
+
+#[derive(Clone,Copy,Default,Debug,PartialEq)]
+struct NoiseStats {
+    std_dev: f32,
+    peak_to_peak: u16,
+}
+
+impl NoiseStats {
+    // wide enough to hide the noise measured at rest
+    fn suggested_deadband(&self) -> u16 {
+        (self.peak_to_peak as f32 / 2.0).max(self.std_dev * 3.0).ceil() as u16
+    }
 }
 
 struct CalSample{
     list: Vec<AdcRawMsg>,
-    rx: TopicReader<AdcRawMsg>,
 }
 
 impl CalSample {
-    fn new(rx:TopicReader<AdcRawMsg>) -> Self {
+    fn new() -> Self {
         CalSample {
             list: Vec::new(),
-            rx,
         }
     }
 
@@ -151,24 +483,44 @@ impl CalSample {
         a.value[channel_index as usize]      
     }
 
-    #[allow(dead_code)]
+    fn get_noise_of_channel(&self, channel_index: u8) -> NoiseStats {
+        let values: Vec<f32> = self.list.iter().map(|x| x.value[channel_index as usize] as f32).collect();
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        let variance = values.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / values.len() as f32;
+        NoiseStats {
+            std_dev: variance.sqrt(),
+            peak_to_peak: (self.get_max_of_channel(channel_index) as i32 - self.get_min_of_channel(channel_index) as i32) as u16,
+        }
+    }
+
     fn get_average(&self) -> AdcRawMsg {
-        let mut ret = self
+        // sum in i32, i16 overflows after a few samples
+        let sum = self
             .list
             .iter()
-            .fold(AdcRawMsg::default(), |acc, x| {
-                let mut tmp = AdcRawMsg::default();
-                for (index, (a, b)) in acc.value.iter().zip(x.value).enumerate() {
-                    tmp.value[index] = a + b;
+            .fold([0i32; ADC_CHANNEL_NUM], |mut acc, x| {
+                for (a, b) in acc.iter_mut().zip(x.value) {
+                    *a += b as i32;
                 }
-                tmp
+                acc
             });
-        for i in ret.value.iter_mut() {
-            *i = (*i) / self.list.len() as i16;
+        let mut ret = AdcRawMsg::default();
+        for (i, s) in ret.value.iter_mut().zip(sum) {
+            *i = (s / self.list.len() as i32) as i16;
         }
+        ret.valid = self.list[0].valid;
+        ret.source = self.list[0].source;
         ret
     }
 
+    // the stick ends at its lowest side, so a value closer to the max of the step means the adc goes the other way
+    fn is_reversed(&self, channel_index: u8) -> bool {
+        let last = self.list.last().unwrap().value[channel_index as usize] as i32;
+        let min = self.get_min_of_channel(channel_index) as i32;
+        let max = self.get_max_of_channel(channel_index) as i32;
+        last - min > max - last
+    }
+
     fn find_largest_change_channel(&self)->u8{
         let cmp_func = |a: &&AdcRawMsg,b: &&AdcRawMsg|{
             let sum_a:i32 = a.value.iter().map(|a| *a as i32).sum();
@@ -181,55 +533,185 @@ impl CalSample {
 
         let mut max_diff=0;
         let mut ret:u8=0;
-        thread_logln!("max:{:?}",max);
-        thread_logln!("min:{:?}",min);
         for (index,(a,b)) in max.value.iter().zip(min.value).enumerate(){
+            if !max.valid[index] {
+                continue;
+            }
             let sub = (*a as i32 - b as i32).abs();
             if sub > max_diff{
                 max_diff = sub;
                 ret = index as u8;
             }
         }
-        thread_logln!("index:{}",ret);
         ret
     }
+}
 
-    fn sample_in_seconds(&mut self, seconds: u32) {
-        let start_time = std::time::Instant::now();
-        loop {
-            let data = self.rx.read();
-            self.list.push(data);
-
-            if start_time.elapsed().as_secs() >= seconds as u64 {
-                break;
+// collects adc_raw into the running calibration and prints live feedback until it ends
+fn run_session(mut rx: TopicReader<AdcRawMsg>) {
+    let mut last_state = None;
+    let mut last_feedback = Instant::now();
+    let mut source_logged = false;
+    loop {
+        let msg = rx.try_read();
+        if let (Some(msg), false) = (msg.as_ref(), source_logged) {
+            source_logged = true;
+            thread_logln!("input source:{:?}, {} channels, range:{:?}", msg.source, msg.channel_num(), msg.source.range());
+        }
+        {
+            let mut guard = CALIBRATION.lock().unwrap();
+            let cal = match guard.as_mut() {
+                Some(cal) => cal,
+                None => break,
+            };
+            if let Some(msg) = msg {
+                cal.sample.list.push(msg);
+            }
+            if last_state != Some(cal.state) {
+                last_state = Some(cal.state);
+                thread_logln!("{}", cal.prompt());
             }
+            if last_feedback.elapsed() >= FEEDBACK_INTERVAL {
+                last_feedback = Instant::now();
+                if let Some(feedback) = cal.feedback() {
+                    thread_logln!("{}", feedback);
+                }
+            }
+        }
+        if msg.is_none() {
+            std::thread::sleep(Duration::from_millis(1));
         }
     }
+    thread_logln!("calibration ended.");
+}
 
-    #[allow(dead_code)]
-    fn sample_by_counts(&mut self, count: u32) {
-        while (self.list.len() as u32) < count {
-            let data = self.rx.read();
-            self.list.push(data);
-        }
+// the session the step commands work on
+fn running(guard: &mut Option<Calibration>) -> Option<&mut Calibration> {
+    if guard.is_none() {
+        thread_logln!("no calibration running, run `calibrate` first.");
     }
+    guard.as_mut()
 }
 
-fn calibrate_main(_argc: u32, _argv: *const &str) {
-    let mut cal = Calibration::new();
-    loop{
-        match cal.state{
-            CalibrateState::Finish => {
-                cal.do_step();
-                break
-            },
-            _ => cal.do_step()
+fn toggle_rev(filename: &str, name: &str) -> Result<(), CalibrationError> {
+    let mut data = CalibrationData::load(filename, &[name])?;
+    let info = data.channel_infos.iter_mut().find(|x| x.name.eq_ignore_ascii_case(name)).unwrap();
+    info.rev = !info.rev;
+    thread_logln!("{} rev:{}, restart mixer to apply.", info.name, info.rev);
+    data.save(filename)
+}
+
+fn calibrate_main(argc: u32, argv: *const &str) {
+    let ret = client_process_args::<Cli>(argc, argv);
+    if ret.is_none() {
+        return;
+    }
+
+    let mut guard = CALIBRATION.lock().unwrap();
+    let args = ret.unwrap();
+    if guard.is_some() && matches!(args.command, None | Some(CalibrateCommand::Channel { .. })) {
+        thread_logln!("calibration is already running, `calibrate abort` first.");
+        return;
+    }
+    match args.command {
+        None => {
+            let partial = !args.input.is_empty();
+            let inputs = if args.input.is_empty() {
+                STICK_INPUTS
+                    .iter()
+                    .map(|(name, kind)| InputSpec { name: name.to_string(), kind: *kind })
+                    .collect()
+            } else {
+                args.input
+            };
+            if let Some(name) = inputs.iter().enumerate().find_map(|(i, x)| {
+                inputs[..i].iter().any(|y| y.name.eq_ignore_ascii_case(&x.name)).then_some(&x.name)
+            }) {
+                thread_logln!("input {} is given twice.", name);
+                return;
+            }
+            if inputs.len() > ADC_CHANNEL_NUM {
+                thread_logln!("at most {} inputs can be calibrated.", ADC_CHANNEL_NUM);
+                return;
+            }
+            // sticks are named after the stick mode in use, or the one of the saved calibration they are merged into
+            let saved = CalibrationData::load(CALIBRATE_FILENAME, &[]).ok();
+            let mode = saved.as_ref().map(|x| if partial { x.calibrated_mode } else { x.stick_mode }).unwrap_or_default();
+            thread_logln!("start calibrate joysticks in stick mode {}!", mode.number());
+            let mut cal = Calibration::new(inputs);
+            cal.data.calibrated_mode = mode;
+            cal.data.stick_mode = mode;
+            if partial {
+                cal.base = saved;
+            }
+            *guard = Some(cal);
+            drop(guard);
+            run_session(adc_raw_subscriber());
+        }
+        Some(CalibrateCommand::Channel { name }) => {
+            let cal = match CalibrationData::load(CALIBRATE_FILENAME, &[]).and_then(|base| Calibration::new_single(base, &name)) {
+                Ok(cal) => cal,
+                Err(e) => {
+                    thread_logln!("{}.", e);
+                    return;
+                }
+            };
+            thread_logln!("start calibrate {}!", cal.inputs[0].name);
+            *guard = Some(cal);
+            drop(guard);
+            run_session(adc_raw_subscriber());
+        }
+        Some(CalibrateCommand::Rev { name }) => {
+            if let Err(e) = toggle_rev(CALIBRATE_FILENAME, &name) {
+                thread_logln!("{}.", e);
+            }
+        }
+        Some(CalibrateCommand::Next) => {
+            let Some(cal) = running(&mut guard) else { return };
+            let confirmed = cal.data.channel_indexs.len();
+            if let Err(e) = cal.next() {
+                thread_logln!("{}", e);
+                return;
+            }
+            if cal.data.channel_indexs.len() > confirmed {
+                thread_logln!("confirmed channel:{}", cal.data.channel_indexs.last().unwrap());
+            }
+            if cal.state == CalibrateState::Finish {
+                for info in cal.data.channel_infos.iter(){
+                    thread_logln!("{}:{:?}",info.name,info);
+                }
+                for line in cal.noise_report() {
+                    thread_logln!("{}", line);
+                }
+                if let Err(e) = cal.save() {
+                    thread_logln!("not saved, {}.", e);
+                    cal.back();
+                    thread_logln!("{}", cal.prompt());
+                    return;
+                }
+                thread_logln!("saved to {}.", CALIBRATE_FILENAME);
+                *guard = None;
+            }
+        }
+        Some(CalibrateCommand::Back) => {
+            let Some(cal) = running(&mut guard) else { return };
+            cal.back();
+            thread_logln!("{}", cal.prompt());
+        }
+        Some(CalibrateCommand::Abort) => {
+            if running(&mut guard).is_some() {
+                *guard = None;
+                thread_logln!("calibration aborted.");
+            }
+        }
+        Some(CalibrateCommand::Status) => {
+            let Some(cal) = running(&mut guard) else { return };
+            thread_logln!("{}", cal.prompt());
+            if let Some(feedback) = cal.feedback() {
+                thread_logln!("{}", feedback);
+            }
         }
     }
-    _ = std::fs::remove_file(CALIBRATE_FILENAME);
-    let mut file = std::fs::OpenOptions::new().read(false).write(true).create_new(true).open(CALIBRATE_FILENAME).unwrap();
-    let str_write = toml::to_string(&cal.data).unwrap();
-    file.write(str_write.as_bytes()).unwrap();
 }
 
 #[rpos::ctor::ctor]
@@ -240,7 +722,7 @@ fn register() {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::msgbus::adc_raw_publisher;
+    use crate::adc::AdcSource;
     use serde::Serialize;
     #[derive(Serialize)]
     struct SaveInfo{
@@ -252,7 +734,8 @@ mod tests {
     fn test_save_cal_data(){
         let mut a = CalibrationData{
             channel_indexs:[0,1,2,3].to_vec(),
-            channel_infos:Vec::new()
+            channel_infos:Vec::new(),
+            ..Default::default()
         };
         a.channel_infos.push(ChannelInfo::default());
         a.channel_infos.push(ChannelInfo::default());
@@ -262,40 +745,61 @@ mod tests {
     }
     #[test]
     fn test_calsample_average() {
-        let rx = adc_raw_subscriber();
-    let mut sample = CalSample::new(rx.clone());
-        sample.list.push(AdcRawMsg { value: [100; 4] });
-        sample.list.push(AdcRawMsg { value: [200; 4] });
+        let mut sample = CalSample::new();
+        sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[100; 4]));
+        sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[200; 4]));
 
         let average = sample.get_average();
 
-        for i in average.value {
-            assert_eq!(i, 150);
+        assert_eq!(average.channel_num(), 4);
+        for i in &average.value[..average.channel_num()] {
+            assert_eq!(*i, 150);
         }
+
+        // center feedback lists the valid channels only, wherever they are
+        let mut cal = Calibration::new(Vec::new());
+        let mut msg = AdcRawMsg::new(AdcSource::Evdev, &[]);
+        msg.value[1] = 10;
+        msg.valid[1] = true;
+        msg.value[3] = -20;
+        msg.valid[3] = true;
+        cal.sample.list.push(msg);
+        assert_eq!(cal.feedback(), Some("center:[1:10 3:-20]".to_string()));
         
     }
 
+    #[test]
+    fn test_calsample_noise() {
+        let mut sample = CalSample::new();
+        for v in [98, 100, 102, 100] {
+            sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[v, 500]));
+        }
+        let noise = sample.get_noise_of_channel(0);
+        assert_eq!(noise.peak_to_peak, 4);
+        assert!((noise.std_dev - 2f32.sqrt()).abs() < 0.001);
+        assert_eq!(noise.suggested_deadband(), 5);
+        assert_eq!(sample.get_noise_of_channel(1), NoiseStats::default());
+    }
+
     #[test]
     fn test_calsample_find_largest_changes_channel(){
-        let rx = adc_raw_subscriber();
-        let mut sample = CalSample::new(rx.clone());
-        sample.list.push(AdcRawMsg { value: [101,99,103,102] });
-        sample.list.push(AdcRawMsg { value: [295,290,301,299] });
+        let mut sample = CalSample::new();
+        sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[101,99,103,102]));
+        sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[295,290,301,299]));
 
         assert_eq!(sample.find_largest_change_channel(),2);
     }
 
     #[test]
     fn test_calsample_get_min_max(){
-        let rx = adc_raw_subscriber();
-        let mut sample = CalSample::new(rx.clone());
-        const CHANNEL_NUM: usize = JoystickChannel::ITER.len();
-        sample.list.push(AdcRawMsg { value: [50;CHANNEL_NUM] });
-        sample.list.push(AdcRawMsg { value: [100;CHANNEL_NUM] });
-        sample.list.push(AdcRawMsg { value: [200;CHANNEL_NUM] });
-        sample.list.push(AdcRawMsg { value: [300;CHANNEL_NUM] });
-        sample.list.push(AdcRawMsg { value: [400;CHANNEL_NUM] });
-        sample.list.push(AdcRawMsg { value: [500;CHANNEL_NUM] });
+        let mut sample = CalSample::new();
+        const CHANNEL_NUM: usize = STICK_INPUTS.len();
+        sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[50;CHANNEL_NUM]));
+        sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[100;CHANNEL_NUM]));
+        sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[200;CHANNEL_NUM]));
+        sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[300;CHANNEL_NUM]));
+        sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[400;CHANNEL_NUM]));
+        sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[500;CHANNEL_NUM]));
 
         for i in 0..CHANNEL_NUM{
             assert_eq!(sample.get_min_of_channel(i as u8),50);
@@ -305,25 +809,329 @@ mod tests {
 
     #[test]
     fn test_calibrate(){
-        let mut cal = Calibration::new();
-        std::thread::spawn(||{
-            let tx = adc_raw_publisher();
+        let inputs = STICK_INPUTS.iter().map(|(name, kind)| InputSpec { name: name.to_string(), kind: *kind }).collect();
+        let mut cal = Calibration::new(inputs);
+        let center = [1000; 4];
+        let push = |chn: usize, value: i16| {
+            let mut v = center;
+            v[chn] = value;
+            AdcRawMsg::new(AdcSource::Unknown, &v)
+        };
 
-            loop{
-                let msg = AdcRawMsg::default();
-                tx.publish(msg);
-                std::thread::sleep(std::time::Duration::from_millis(100));
-            }
-        });
+        assert!(cal.next().is_err());
+        cal.sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[1000, 990, 1010, 1020]));
+        cal.sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[1000, 1010, 1030, 1000]));
+        assert!(cal.next().is_ok());
+        // thrust on adc channel 2, direction 0, aileron 3, elevator 1
+        for chn in [2, 0, 3, 1] {
+            assert!(cal.next().is_err());
+            cal.sample.list.push(push(chn, 1000));
+            cal.sample.list.push(push(chn, 200));
+            cal.next().unwrap();
+        }
+        // a channel can't be assigned twice
+        cal.back();
+        cal.sample.list.push(push(2, 1000));
+        cal.sample.list.push(push(2, 200));
+        assert!(cal.next().is_err());
+        cal.sample.list.clear();
+        cal.sample.list.push(push(1, 200));
+        cal.sample.list.push(push(1, 1000));
+        cal.next().unwrap();
+        assert_eq!(cal.data.channel_indexs, [2, 0, 3, 1]);
+        assert_eq!(cal.state, CalibrateState::MinMaxCheck);
 
-        loop{
-            match cal.state{
-                CalibrateState::Finish => {
-                    cal.do_step();
-                    break
-                },
-                _ => cal.do_step()
-            }
+        // redo the elevator step
+        cal.back();
+        assert_eq!(cal.state, CalibrateState::LowestCheck(3));
+        assert_eq!(cal.data.channel_indexs, [2, 0, 3]);
+        cal.sample.list.push(push(1, 1000));
+        cal.sample.list.push(push(1, 300));
+        cal.next().unwrap();
+
+        for chn in 0..4 {
+            cal.sample.list.push(push(chn, 100 + chn as i16));
+            cal.sample.list.push(push(chn, 1900 - chn as i16));
         }
+        assert!(cal.feedback().is_some());
+        cal.next().unwrap();
+        assert_eq!(cal.state, CalibrateState::Finish);
+        assert_eq!(cal.data.channel_infos[0].index, 2);
+        assert_eq!(cal.data.channel_infos[0].min, 102);
+        assert_eq!(cal.data.channel_infos[0].max, 1898);
+        assert_eq!(cal.data.channel_infos[0].center, None);
+        assert_eq!(cal.data.channel_infos[3].index, 1);
+        assert_eq!(cal.data.channel_infos[3].center, Some(1000));
+        assert_eq!(cal.data.channel_infos[2].center, Some(1010));
+        // center samples of aileron were 1020 and 1000
+        assert_eq!(cal.data.channel_infos[2].deadband, 30);
+        assert_eq!(cal.data.channel_infos[0].deadband, 0);
+        let report = cal.noise_report();
+        assert!(report[2].starts_with("Aileron: std-dev:10.0 p2p:20 deadband:30"));
+        assert!(!report[2].contains("NOISY"));
+        // the same noise over a short range is flagged
+        cal.data.channel_infos[2].max = 600;
+        assert!(cal.noise_report()[2].ends_with("NOISY, the pot may be worn"));
+        cal.data.channel_infos[2].max = 1897;
+        assert_eq!(cal.data.validate(&["thrust", "Elevator"]), Ok(()));
+        assert_eq!(cal.data.get("aileron").unwrap().kind, InputKind::Centering);
+    }
+
+    #[test]
+    fn test_calibrate_named_inputs() {
+        let mut cal = Calibration::new(vec![
+            parse_input_spec("Pot1:non-centering").unwrap(),
+            parse_input_spec("Slider:non-centering").unwrap(),
+            parse_input_spec("Yaw:centering").unwrap(),
+        ]);
+        let push = |chn: usize, value: i16| {
+            let mut v = [500; 6];
+            v[chn] = value;
+            AdcRawMsg::new(AdcSource::Unknown, &v)
+        };
+
+        cal.sample.list.push(push(0, 500));
+        cal.next().unwrap();
+        for (chn, lowest) in [(5, 0), (4, 1000), (1, 0)] {
+            cal.sample.list.push(push(chn, 500));
+            cal.sample.list.push(push(chn, lowest));
+            cal.next().unwrap();
+        }
+        assert_eq!(cal.state, CalibrateState::MinMaxCheck);
+        for chn in 0..6 {
+            cal.sample.list.push(push(chn, 10));
+            cal.sample.list.push(push(chn, 1000));
+        }
+        cal.next().unwrap();
+        assert_eq!(cal.state, CalibrateState::Finish);
+        assert_eq!(cal.data.channel_infos.len(), 3);
+        assert_eq!(cal.data.get("pot1").unwrap().index, 5);
+        assert_eq!(cal.data.get("Slider").unwrap().center, None);
+        assert_eq!(cal.data.get("Yaw").unwrap().center, Some(500));
+        // Slider went up when pushed to its lowest side
+        assert!(!cal.data.get("Pot1").unwrap().rev);
+        assert!(cal.data.get("Slider").unwrap().rev);
+        assert!(!cal.data.get("Yaw").unwrap().rev);
+        assert!(cal.data.get("Thrust").is_none());
+        assert_eq!(
+            cal.data.validate(&["Thrust"]),
+            Err(CalibrationError::MissingChannel("Thrust".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_toggle_rev() {
+        let filename = std::env::temp_dir().join("test_toggle_rev.toml");
+        let filename = filename.to_str().unwrap();
+        let data = CalibrationData {
+            channel_infos: vec![ChannelInfo { name: "Pot1".to_string(), index: 4, min: 0, max: 100, ..Default::default() }],
+            channel_indexs: vec![4],
+            ..Default::default()
+        };
+        data.save(filename).unwrap();
+
+        toggle_rev(filename, "pot1").unwrap();
+        assert!(CalibrationData::load(filename, &[]).unwrap().channel_infos[0].rev);
+        toggle_rev(filename, "Pot1").unwrap();
+        assert!(!CalibrationData::load(filename, &[]).unwrap().channel_infos[0].rev);
+        assert_eq!(toggle_rev(filename, "Pot2"), Err(CalibrationError::MissingChannel("Pot2".to_string())));
+        _ = std::fs::remove_file(filename);
+    }
+
+    #[test]
+    fn test_calibrate_single_channel() {
+        let info = |name: &str, index: u8, rev: bool| ChannelInfo {
+            name: name.to_string(),
+            kind: InputKind::Centering,
+            index,
+            min: 100,
+            max: 900,
+            center: Some(500),
+            rev,
+            ..Default::default()
+        };
+        let base = CalibrationData {
+            channel_infos: vec![info("Aileron", 0, false), info("Elevator", 1, true)],
+            channel_indexs: vec![0, 1],
+            ..Default::default()
+        };
+        assert!(Calibration::new_single(base.clone(), "Thrust").is_err());
+
+        let mut cal = Calibration::new_single(base, "elevator").unwrap();
+        cal.sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[0, 600]));
+        cal.next().unwrap();
+        assert_eq!(cal.state, CalibrateState::MinMaxCheck);
+        cal.back();
+        assert_eq!(cal.state, CalibrateState::Idle);
+        assert_eq!(cal.data.channel_indexs, [1]);
+        cal.sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[0, 600]));
+        cal.next().unwrap();
+
+        cal.sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[0, 50]));
+        cal.sample.list.push(AdcRawMsg::new(AdcSource::Unknown, &[0, 1000]));
+        cal.next().unwrap();
+        assert_eq!(cal.state, CalibrateState::Finish);
+
+        let result = cal.result();
+        assert_eq!(result.channel_infos.len(), 2);
+        assert_eq!(result.channel_infos[0].max, 900);
+        let elevator = result.get("Elevator").unwrap();
+        assert_eq!((elevator.index, elevator.min, elevator.max, elevator.center, elevator.rev), (1, 50, 1000, Some(600), true));
+    }
+
+    #[test]
+    fn test_calibrate_merge_inputs() {
+        let base = CalibrationData {
+            channel_infos: vec![
+                ChannelInfo { name: "Aileron".to_string(), index: 0, min: 100, max: 900, ..Default::default() },
+                ChannelInfo { name: "Pot1".to_string(), index: 4, min: 0, max: 100, ..Default::default() },
+            ],
+            channel_indexs: vec![0, 4],
+            ..Default::default()
+        };
+        let mut cal = Calibration::new(vec![
+            InputSpec { name: "pot1".to_string(), kind: InputKind::NonCentering },
+            InputSpec { name: "Slider1".to_string(), kind: InputKind::NonCentering },
+        ]);
+        cal.base = Some(base);
+        let push = |chn: usize, value: i16| {
+            let mut v = [500; 7];
+            v[chn] = value;
+            AdcRawMsg::new(AdcSource::Unknown, &v)
+        };
+
+        // the adc channels are detected, not taken from the saved calibration
+        cal.sample.list.push(push(0, 500));
+        cal.next().unwrap();
+        assert_eq!(cal.state, CalibrateState::LowestCheck(0));
+        cal.sample.list.push(push(5, 10));
+        cal.sample.list.push(push(5, 900));
+        cal.next().unwrap();
+        // Aileron is kept and still reads adc channel 0
+        cal.sample.list.push(push(0, 10));
+        cal.sample.list.push(push(0, 900));
+        assert!(cal.next().is_err());
+        assert_eq!(cal.state, CalibrateState::LowestCheck(1));
+        cal.sample.list.clear();
+        cal.sample.list.push(push(6, 20));
+        cal.sample.list.push(push(6, 900));
+        cal.next().unwrap();
+        assert_eq!(cal.state, CalibrateState::MinMaxCheck);
+        cal.back();
+        assert_eq!(cal.state, CalibrateState::LowestCheck(1));
+        cal.sample.list.push(push(6, 20));
+        cal.sample.list.push(push(6, 900));
+        cal.next().unwrap();
+
+        cal.sample.list.push(push(5, 10));
+        cal.sample.list.push(push(5, 1010));
+        cal.sample.list.push(push(6, 20));
+        cal.sample.list.push(push(6, 2020));
+        cal.next().unwrap();
+        assert_eq!(cal.state, CalibrateState::Finish);
+
+        // Aileron is kept, Pot1 is replaced, Slider1 is added
+        let result = cal.result();
+        assert_eq!(result.channel_infos.len(), 3);
+        assert_eq!(result.channel_indexs, [0, 5, 6]);
+        assert_eq!(result.get("Aileron").unwrap().max, 900);
+        let pot = result.get("Pot1").unwrap();
+        assert_eq!((pot.index, pot.min, pot.max), (5, 10, 1010));
+        assert_eq!(result.get("Slider1").unwrap().max, 2020);
+        assert_eq!(result.validate(&["Aileron", "Pot1", "Slider1"]), Ok(()));
+    }
+
+    #[test]
+    fn test_save_backup() {
+        let filename = std::env::temp_dir().join("test_save_backup.toml");
+        let filename = filename.to_str().unwrap();
+        let backup = format!("{}.bak", filename);
+        _ = std::fs::remove_file(filename);
+        _ = std::fs::remove_file(&backup);
+        let mut data = CalibrationData {
+            channel_infos: vec![ChannelInfo { name: "Pot1".to_string(), index: 4, min: 0, max: 100, ..Default::default() }],
+            channel_indexs: vec![4],
+            ..Default::default()
+        };
+        data.save(filename).unwrap();
+        assert!(!std::path::Path::new(&backup).exists());
+
+        data.channel_infos[0].max = 200;
+        data.save(filename).unwrap();
+        assert_eq!(CalibrationData::load(filename, &[]).unwrap().channel_infos[0].max, 200);
+        assert_eq!(CalibrationData::load(&backup, &[]).unwrap().channel_infos[0].max, 100);
+        assert!(!std::path::Path::new(&format!("{}.tmp", filename)).exists());
+        _ = std::fs::remove_file(filename);
+        _ = std::fs::remove_file(backup);
+    }
+
+    #[test]
+    fn test_parse_input_spec() {
+        assert_eq!(
+            parse_input_spec("Pot1:centering").unwrap(),
+            InputSpec { name: "Pot1".to_string(), kind: InputKind::Centering }
+        );
+        assert!(parse_input_spec("Pot1").is_err());
+        assert!(parse_input_spec(":centering").is_err());
+        assert!(parse_input_spec("Pot1:spring").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let info = |name: &str, index: u8| ChannelInfo {
+            name: name.to_string(),
+            kind: InputKind::NonCentering,
+            index,
+            min: 100,
+            max: 1000,
+            center: None,
+            deadband: 0,
+            rev: false,
+        };
+        let mut data = CalibrationData {
+            channel_infos: vec![info("Thrust", 0), info("Direction", 1), info("Aileron", 2)],
+            channel_indexs: vec![0, 1, 2],
+            ..Default::default()
+        };
+        let required = ["Thrust", "Direction", "Aileron", "Elevator"];
+        assert_eq!(data.validate(&required), Err(CalibrationError::MissingChannel("Elevator".to_string())));
+
+        data.channel_infos.push(info("aileron", 3));
+        assert_eq!(data.validate(&[]), Err(CalibrationError::DuplicateName("aileron".to_string())));
+
+        data.channel_infos[3].name = "Elevator".to_string();
+        assert_eq!(data.validate(&required), Ok(()));
+
+        data.channel_infos[3].index = ADC_CHANNEL_NUM as u8;
+        assert_eq!(
+            data.validate(&required),
+            Err(CalibrationError::IndexOutOfRange { name: "Elevator".to_string(), index: ADC_CHANNEL_NUM as u8 })
+        );
+
+        data.channel_infos[3].index = 1;
+        assert_eq!(
+            data.validate(&required),
+            Err(CalibrationError::DuplicateIndex {
+                first: "Direction".to_string(),
+                second: "Elevator".to_string(),
+                index: 1
+            })
+        );
+
+        data.channel_infos[3].index = 3;
+        data.channel_infos[2].max = 100;
+        assert_eq!(
+            data.validate(&required),
+            Err(CalibrationError::EmptyRange { name: "Aileron".to_string(), min: 100, max: 100 })
+        );
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        assert!(matches!(CalibrationData::load("no_such_file.toml", &[]), Err(CalibrationError::Io(_))));
+        let filename = std::env::temp_dir().join("test_load_invalid.toml");
+        std::fs::write(&filename, "channel_infos = 1").unwrap();
+        assert!(matches!(CalibrationData::load(filename.to_str().unwrap(), &[]), Err(CalibrationError::Parse(_))));
+        _ = std::fs::remove_file(filename);
     }
 }
@@ -0,0 +1,165 @@
+// both axes use the 0 ~ 10000 scale of MixerOutMsg
+pub const CURVE_MAX: i32 = 10000;
+pub const CURVE_POINTS: [usize; 4] = [3, 5, 9, 17];
+
+// model file, e.g. a throttle curve with a flat middle
+// [[curves]]
+// name = "thr_mid"
+// y = [0, 4500, 5000, 5500, 10000]
+// smooth = true
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct Curve {
+    pub name: String,
+    pub y: Vec<i32>,
+    // increasing, from 0 to 10000. evenly spaced when omitted
+    #[serde(default)]
+    pub x: Option<Vec<i32>>,
+    // cubic interpolation through the points instead of straight lines
+    #[serde(default)]
+    pub smooth: bool,
+}
+
+impl Curve {
+    pub fn validate(&self) -> Result<(), String> {
+        let n = self.y.len();
+        if !CURVE_POINTS.contains(&n) {
+            return Err(format!("curve {} has {} points, should be one of {:?}", self.name, n, CURVE_POINTS));
+        }
+        if let Some(y) = self.y.iter().find(|y| !(0..=CURVE_MAX).contains(*y)) {
+            return Err(format!("curve {}: y {} should be 0 ~ {}", self.name, y, CURVE_MAX));
+        }
+        if let Some(x) = &self.x {
+            if x.len() != n {
+                return Err(format!("curve {} has {} x and {} y", self.name, x.len(), n));
+            }
+            if x[0] != 0 || x[n - 1] != CURVE_MAX || x.windows(2).any(|w| w[0] >= w[1]) {
+                return Err(format!("curve {}: x should increase from 0 to {}", self.name, CURVE_MAX));
+            }
+        }
+        Ok(())
+    }
+
+    fn x(&self, index: usize) -> i32 {
+        match &self.x {
+            Some(x) => x[index],
+            None => index as i32 * CURVE_MAX / (self.y.len() as i32 - 1),
+        }
+    }
+
+    // slope at a point times the segment width `d`, from its neighbours
+    fn tangent(&self, index: usize, d: i64) -> i64 {
+        let prev = index.saturating_sub(1);
+        let next = (index + 1).min(self.y.len() - 1);
+        let dy = (self.y[next] - self.y[prev]) as i64;
+        let dx = (self.x(next) - self.x(prev)) as i64;
+        d * dy / dx
+    }
+
+    // x: 0 ~ 10000, returns 0 ~ 10000
+    pub fn eval(&self, x: i32) -> i32 {
+        let x = x.clamp(0, CURVE_MAX);
+        let last = self.y.len() - 1;
+        let index = (0..last).find(|i| x <= self.x(i + 1)).unwrap_or(last - 1);
+        let (x0, x1) = (self.x(index), self.x(index + 1));
+        let (y0, y1) = (self.y[index] as i64, self.y[index + 1] as i64);
+        let d = (x1 - x0) as i64;
+        let s = (x - x0) as i64;
+        if !self.smooth {
+            return (y0 + (y1 - y0) * s / d) as i32;
+        }
+
+        // cubic hermite with t = s / d, every term multiplied by d^3
+        let (s2, s3, d2, d3) = (s * s, s * s * s, d * d, d * d * d);
+        let h00 = 2 * s3 - 3 * s2 * d + d3;
+        let h10 = s3 - 2 * s2 * d + s * d2;
+        let h01 = -2 * s3 + 3 * s2 * d;
+        let h11 = s3 - s2 * d;
+        let y = (h00 * y0 + h10 * self.tangent(index, d) + h01 * y1 + h11 * self.tangent(index + 1, d)) / d3;
+        (y as i32).clamp(0, CURVE_MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(y: &[i32], x: Option<&[i32]>, smooth: bool) -> Curve {
+        Curve {
+            name: "test".to_string(),
+            y: y.to_vec(),
+            x: x.map(|x| x.to_vec()),
+            smooth,
+        }
+    }
+
+    #[test]
+    fn test_linear_curve() {
+        let c = curve(&[0, 4500, 5000, 5500, 10000], None, false);
+        assert!(c.validate().is_ok());
+        assert_eq!(c.eval(0), 0);
+        assert_eq!(c.eval(1250), 2250);
+        assert_eq!(c.eval(2500), 4500);
+        assert_eq!(c.eval(5000), 5000);
+        assert_eq!(c.eval(6250), 5250);
+        assert_eq!(c.eval(10000), 10000);
+        assert_eq!(c.eval(-100), 0);
+        assert_eq!(c.eval(20000), 10000);
+
+        // a straight 17 point line is the identity
+        let y: Vec<i32> = (0..17).map(|i| i * 625).collect();
+        let c = curve(&y, None, false);
+        assert!(c.validate().is_ok());
+        for x in (0..=10000).step_by(77) {
+            assert_eq!(c.eval(x), x);
+        }
+    }
+
+    #[test]
+    fn test_custom_x() {
+        let c = curve(&[10000, 2000, 0], Some(&[0, 8000, 10000]), false);
+        assert!(c.validate().is_ok());
+        assert_eq!(c.eval(4000), 6000);
+        assert_eq!(c.eval(8000), 2000);
+        assert_eq!(c.eval(9000), 1000);
+    }
+
+    #[test]
+    fn test_smooth_curve() {
+        let c = curve(&[0, 2000, 5000, 8000, 10000], None, true);
+        // passes through every point
+        for i in 0..5 {
+            assert_eq!(c.eval(c.x(i)), c.y[i]);
+        }
+        // straight points stay straight
+        let c = curve(&[0, 5000, 10000], None, true);
+        for x in (0..=10000).step_by(100) {
+            assert!((c.eval(x) - x).abs() <= 1);
+        }
+
+        // monotonic points give a monotonic curve here, and differ from the linear one between points
+        let c = curve(&[0, 1000, 5000, 9000, 10000], None, true);
+        let linear = curve(&[0, 1000, 5000, 9000, 10000], None, false);
+        let mut last = 0;
+        for x in (0..=10000).step_by(50) {
+            let y = c.eval(x);
+            assert!(y >= last);
+            last = y;
+        }
+        assert_ne!(c.eval(3750), linear.eval(3750));
+
+        // overshoot is clamped
+        let c = curve(&[0, 10000, 10000], None, true);
+        assert!((0..=10000).all(|x| (0..=CURVE_MAX).contains(&c.eval(x))));
+    }
+
+    #[test]
+    fn test_validate_curve() {
+        assert!(curve(&[0, 10000], None, false).validate().is_err());
+        assert!(curve(&[0, 5000, 10000, 10000], None, false).validate().is_err());
+        assert!(curve(&[0, 5000, 10001], None, false).validate().is_err());
+        assert!(curve(&[0, 5000, 10000], Some(&[0, 10000]), false).validate().is_err());
+        assert!(curve(&[0, 5000, 10000], Some(&[0, 6000, 9000]), false).validate().is_err());
+        assert!(curve(&[0, 5000, 10000], Some(&[0, 6000, 6000]), false).validate().is_err());
+        assert!(curve(&[0, 5000, 10000], Some(&[0, 6000, 10000]), false).validate().is_ok());
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use morb::Publisher;
+
+use crate::msgbus::device_status_publisher;
+
+const BACKOFF_MIN: Duration = Duration::from_millis(100);
+const BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct DeviceStatusMsg {
+    // module name, e.g. joy_dev
+    pub module: String,
+    pub path: String,
+    pub connected: bool,
+}
+
+// retry delay that doubles after each failure, up to BACKOFF_MAX
+pub struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Backoff { delay: BACKOFF_MIN }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let ret = self.delay;
+        self.delay = (self.delay * 2).min(BACKOFF_MAX);
+        ret
+    }
+
+    pub fn reset(&mut self) {
+        self.delay = BACKOFF_MIN;
+    }
+}
+
+// publishes DeviceStatusMsg only when the state changes
+pub struct DeviceStatus {
+    module: &'static str,
+    path: String,
+    connected: Option<bool>,
+    tx: Publisher<DeviceStatusMsg>,
+}
+
+impl DeviceStatus {
+    pub fn new(module: &'static str, path: &str) -> Self {
+        DeviceStatus {
+            module,
+            path: path.to_string(),
+            connected: None,
+            tx: device_status_publisher(),
+        }
+    }
+
+    pub fn set(&mut self, connected: bool) {
+        if self.connected == Some(connected) {
+            return;
+        }
+        self.connected = Some(connected);
+        rpos::thread_logln!(
+            "{}: {} {}",
+            self.module,
+            self.path,
+            if connected { "connected" } else { "disconnected, retrying" }
+        );
+        self.tx.publish(DeviceStatusMsg {
+            module: self.module.to_string(),
+            path: self.path.clone(),
+            connected,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new();
+        assert_eq!(backoff.next_delay(), BACKOFF_MIN);
+        assert_eq!(backoff.next_delay(), BACKOFF_MIN * 2);
+        for _ in 0..20 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.next_delay(), BACKOFF_MAX);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), BACKOFF_MIN);
+    }
+}
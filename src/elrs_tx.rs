@@ -1,11 +1,15 @@
-use std::time::Duration;
+use std::{io::Write, time::Duration};
 
 use clap::Parser;
 use crc::{Crc, CRC_8_DVB_S2};
 use crsf::{PacketAddress, RawPacket};
 use rpos::{pthread_scheduler::SchedulePthread, thread_logln};
 
-use crate::{client_process_args, msgbus::mixer_out_subscriber};
+use crate::{
+    client_process_args,
+    device::{Backoff, DeviceStatus},
+    msgbus::mixer_out_subscriber,
+};
 
 #[derive(Parser)]
 #[command(name="erls_tx", about = None, long_about = None)]
@@ -42,6 +46,21 @@ fn mxier_out_2_crsf(val:u16) -> u16{
     (val as u32  * (crsf::RcChannels::CHANNEL_VALUE_MAX - crsf::RcChannels::CHANNEL_VALUE_MIN) as u32 / 10000 + crsf::RcChannels::CHANNEL_VALUE_MIN as u32) as u16
 }
 
+fn open_elrs(dev_name: &str, baudrate: u32) -> Result<Box<dyn serialport::SerialPort>, String> {
+    let serial = serialport::new(dev_name, baudrate);
+    let mut dev = serial
+        .timeout(Duration::from_millis(1000))
+        .open()
+        .map_err(|e| e.to_string())?;
+
+    let magic_cmd = gen_magic_packet();
+    for _ in 0..10 {
+        dev.write_all(&magic_cmd).map_err(|e| e.to_string())?;
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    Ok(dev)
+}
+
 fn elrs_tx_main(argc: u32, argv: *const &str) {
     let arg_ret = client_process_args::<Cli>(argc, argv);
     if arg_ret.is_none() {
@@ -49,30 +68,41 @@ fn elrs_tx_main(argc: u32, argv: *const &str) {
     }
 
     let args = arg_ret.unwrap();
-
-    let dev_name = &args.dev_name;
-    let serial = serialport::new(dev_name, args.baudrate);
-    let mut dev = serial.timeout(Duration::from_millis(1000)).open().unwrap();
     let mut rx = mixer_out_subscriber();
 
-    let magic_cmd = gen_magic_packet();
-    for _ in 0..10 {
-        dev.write(&magic_cmd).unwrap();
-        std::thread::sleep(std::time::Duration::from_millis(10));
-    }
-
     thread_logln!("elrs_tx start!");
 
     SchedulePthread::new_simple(Box::new(move |_| {
         let mut crsf_chn_values:[u16;16] = [0;16];
+        let mut status = DeviceStatus::new("elrs_tx", &args.dev_name);
+        let mut backoff = Backoff::new();
+        let mut dev = None;
         loop {
+            if dev.is_none() {
+                match open_elrs(&args.dev_name, args.baudrate) {
+                    Ok(d) => {
+                        dev = Some(d);
+                        status.set(true);
+                        backoff.reset();
+                    }
+                    Err(_) => {
+                        status.set(false);
+                        std::thread::sleep(backoff.next_delay());
+                        continue;
+                    }
+                }
+            }
+
             let msg = rx.read();
-            crsf_chn_values[0] = mxier_out_2_crsf(msg.aileron);
-            crsf_chn_values[1] = mxier_out_2_crsf(msg.elevator);
-            crsf_chn_values[2] = mxier_out_2_crsf(msg.thrust);
-            crsf_chn_values[3] = mxier_out_2_crsf(msg.direction);
+            for (crsf_value, value) in crsf_chn_values.iter_mut().zip(msg.channels) {
+                *crsf_value = mxier_out_2_crsf(value);
+            }
             let raw_packet = new_rc_channel_packet(&crsf_chn_values);
-            dev.write(raw_packet.data()).unwrap();
+            if dev.as_mut().unwrap().write_all(raw_packet.data()).is_err() {
+                dev = None;
+                status.set(false);
+                continue;
+            }
             std::thread::sleep(std::time::Duration::from_millis(10));
         }
     }));
@@ -0,0 +1,227 @@
+use std::{collections::HashMap, fs, str::FromStr};
+
+use clap::Parser;
+use evdev::{AbsoluteAxisType, InputEventKind, Key};
+use rpos::thread_logln;
+
+use crate::{
+    adc::{now_us, AdcRawMsg, AdcSource, ADC_CHANNEL_NUM},
+    client_process_args,
+    msgbus::{adc_raw_publisher, button_publisher},
+};
+
+pub const BUTTON_NUM: usize = u32::BITS as usize;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ButtonMsg {
+    // bit n is set when the button mapped to n is pressed
+    pub value: u32,
+}
+
+#[derive(Parser)]
+#[command(name="evdev", about = "used for input devices exposed by evdev(/dev/input/event*)", long_about = None)]
+struct Cli {
+    #[arg(short, long, default_value = "evdev.toml")]
+    config: String,
+
+    dev_name: String,
+}
+
+// axis and button names are the kernel ones, e.g. ABS_X = 0, BTN_SOUTH = 0
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct EvdevMapping {
+    #[serde(default)]
+    axes: HashMap<String, usize>,
+    #[serde(default)]
+    buttons: HashMap<String, usize>,
+}
+
+struct EvdevMap {
+    axes: HashMap<u16, usize>,
+    buttons: HashMap<u16, usize>,
+}
+
+impl EvdevMap {
+    fn from_mapping(mapping: &EvdevMapping) -> Result<Self, String> {
+        let mut axes = HashMap::new();
+        for (name, index) in &mapping.axes {
+            let axis = AbsoluteAxisType::from_str(name).map_err(|_| format!("unknown axis:{}", name))?;
+            if *index >= ADC_CHANNEL_NUM {
+                return Err(format!("axis {} mapped to channel {}, only {} channels available", name, index, ADC_CHANNEL_NUM));
+            }
+            axes.insert(axis.0, *index);
+        }
+
+        let mut buttons = HashMap::new();
+        for (name, index) in &mapping.buttons {
+            let key = Key::from_str(name).map_err(|_| format!("unknown button:{}", name))?;
+            if *index >= BUTTON_NUM {
+                return Err(format!("button {} mapped to {}, only {} buttons available", name, index, BUTTON_NUM));
+            }
+            buttons.insert(key.0, *index);
+        }
+
+        Ok(EvdevMap { axes, buttons })
+    }
+}
+
+// axis value within the absinfo min ~ max of the device -> i16 range
+fn normalize_axis(value: i32, min: i32, max: i32) -> i16 {
+    if max <= min {
+        return value.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+    }
+    let value = value.clamp(min, max) as i64 - min as i64;
+    (value * u16::MAX as i64 / (max as i64 - min as i64) + i16::MIN as i64) as i16
+}
+
+fn evdev_main(argc: u32, argv: *const &str) {
+    let ret = client_process_args::<Cli>(argc, argv);
+    if ret.is_none() {
+        return;
+    }
+
+    let args = ret.unwrap();
+    let toml_str = match fs::read_to_string(&args.config) {
+        Ok(s) => s,
+        Err(_) => {
+            thread_logln!("no {} found. please write the axis mapping first!", args.config);
+            return;
+        }
+    };
+    let mapping = match toml::from_str::<EvdevMapping>(toml_str.as_str()) {
+        Ok(m) => m,
+        Err(e) => {
+            thread_logln!("failed to parse {}: {}", args.config, e);
+            return;
+        }
+    };
+    let map = match EvdevMap::from_mapping(&mapping) {
+        Ok(m) => m,
+        Err(e) => {
+            thread_logln!("invalid mapping in {}: {}", args.config, e);
+            return;
+        }
+    };
+
+    let mut dev = match evdev::Device::open(&args.dev_name) {
+        Ok(dev) => dev,
+        Err(e) => {
+            thread_logln!("failed to open {}: {}", args.dev_name, e);
+            return;
+        }
+    };
+    thread_logln!("evdev {} opened: {}", args.dev_name, dev.name().unwrap_or("unknown"));
+    let abs_info = match dev.get_abs_state() {
+        Ok(info) => info,
+        Err(e) => {
+            thread_logln!("failed to read the axis ranges of {}: {}", args.dev_name, e);
+            return;
+        }
+    };
+
+    let adc_raw_tx = adc_raw_publisher();
+    let button_tx = button_publisher();
+    let mut chn_value = AdcRawMsg {
+        source: AdcSource::Evdev,
+        ..Default::default()
+    };
+    let mut buttons = ButtonMsg::default();
+
+    loop {
+        let mut axis_changed = false;
+        let mut button_changed = false;
+        let events = match dev.fetch_events() {
+            Ok(events) => events,
+            Err(e) => {
+                thread_logln!("evdev {} read failed: {}", args.dev_name, e);
+                return;
+            }
+        };
+        for event in events {
+            match event.kind() {
+                InputEventKind::AbsAxis(axis) => {
+                    if let Some(index) = map.axes.get(&axis.0) {
+                        let info = &abs_info[axis.0 as usize];
+                        chn_value.value[*index] = normalize_axis(event.value(), info.minimum, info.maximum);
+                        chn_value.valid[*index] = true;
+                        axis_changed = true;
+                    }
+                }
+                InputEventKind::Key(key) => {
+                    if let Some(index) = map.buttons.get(&key.0) {
+                        if event.value() != 0 {
+                            buttons.value |= 1 << index;
+                        } else {
+                            buttons.value &= !(1 << index);
+                        }
+                        button_changed = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if axis_changed {
+            chn_value.timestamp = now_us();
+            adc_raw_tx.publish(chn_value);
+        }
+        if button_changed {
+            button_tx.publish(buttons);
+        }
+    }
+}
+
+#[rpos::ctor::ctor]
+fn register() {
+    rpos::module::Module::register("evdev", evdev_main);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evdev_mapping() {
+        let mapping = toml::from_str::<EvdevMapping>(
+            r#"
+            [axes]
+            ABS_X = 0
+            ABS_RY = 3
+            [buttons]
+            BTN_SOUTH = 5
+            "#,
+        )
+        .unwrap();
+        let map = EvdevMap::from_mapping(&mapping).unwrap();
+        assert_eq!(map.axes[&AbsoluteAxisType::ABS_X.0], 0);
+        assert_eq!(map.axes[&AbsoluteAxisType::ABS_RY.0], 3);
+        assert_eq!(map.buttons[&Key::BTN_SOUTH.0], 5);
+    }
+
+    #[test]
+    fn test_normalize_axis() {
+        assert_eq!(normalize_axis(0, 0, 255), i16::MIN);
+        assert_eq!(normalize_axis(255, 0, 255), i16::MAX);
+        assert_eq!(normalize_axis(128, 0, 255), 128);
+        assert_eq!(normalize_axis(-512, -512, 511), i16::MIN);
+        assert_eq!(normalize_axis(511, -512, 511), i16::MAX);
+        assert_eq!(normalize_axis(1000, -512, 511), i16::MAX);
+        assert_eq!(normalize_axis(i16::MIN as i32, i16::MIN as i32, i16::MAX as i32), i16::MIN);
+        assert_eq!(normalize_axis(-3, 0, 0), -3);
+    }
+
+    #[test]
+    fn test_evdev_mapping_invalid() {
+        let mut mapping = EvdevMapping::default();
+        mapping.axes.insert("ABS_NOT_EXIST".to_string(), 0);
+        assert!(EvdevMap::from_mapping(&mapping).is_err());
+
+        let mut mapping = EvdevMapping::default();
+        mapping.axes.insert("ABS_X".to_string(), ADC_CHANNEL_NUM);
+        assert!(EvdevMap::from_mapping(&mapping).is_err());
+
+        let mut mapping = EvdevMapping::default();
+        mapping.buttons.insert("BTN_SOUTH".to_string(), 32);
+        assert!(EvdevMap::from_mapping(&mapping).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::VecDeque;
+
+pub trait Filter: Send {
+    fn update(&mut self, x: i16) -> i16;
+}
+
+pub struct MovingAverage {
+    buf: VecDeque<i16>,
+    size: usize,
+    sum: i32,
+}
+
+impl MovingAverage {
+    pub fn new(size: usize) -> Self {
+        MovingAverage {
+            buf: VecDeque::with_capacity(size),
+            size: size.max(1),
+            sum: 0,
+        }
+    }
+}
+
+impl Filter for MovingAverage {
+    fn update(&mut self, x: i16) -> i16 {
+        if self.buf.len() == self.size {
+            self.sum -= self.buf.pop_front().unwrap() as i32;
+        }
+        self.buf.push_back(x);
+        self.sum += x as i32;
+        (self.sum / self.buf.len() as i32) as i16
+    }
+}
+
+pub struct Median {
+    buf: VecDeque<i16>,
+    size: usize,
+}
+
+impl Median {
+    pub fn new(size: usize) -> Self {
+        Median {
+            buf: VecDeque::with_capacity(size),
+            size: size.max(1),
+        }
+    }
+}
+
+impl Filter for Median {
+    fn update(&mut self, x: i16) -> i16 {
+        if self.buf.len() == self.size {
+            self.buf.pop_front();
+        }
+        self.buf.push_back(x);
+        let mut sorted: Vec<i16> = self.buf.iter().copied().collect();
+        sorted.sort_unstable();
+        sorted[sorted.len() / 2]
+    }
+}
+
+// first-order low-pass: y += alpha * (x - y)
+pub struct LowPass {
+    alpha: f32,
+    state: Option<f32>,
+}
+
+impl LowPass {
+    pub fn new(alpha: f32) -> Self {
+        LowPass {
+            alpha: alpha.clamp(0.0, 1.0),
+            state: None,
+        }
+    }
+}
+
+impl Filter for LowPass {
+    fn update(&mut self, x: i16) -> i16 {
+        let y = match self.state {
+            Some(y) => y + self.alpha * (x as f32 - y),
+            None => x as f32,
+        };
+        self.state = Some(y);
+        y.round() as i16
+    }
+}
+
+// output only follows the input once it moved more than threshold away
+pub struct Hysteresis {
+    threshold: i16,
+    output: Option<i16>,
+}
+
+impl Hysteresis {
+    pub fn new(threshold: i16) -> Self {
+        Hysteresis {
+            threshold,
+            output: None,
+        }
+    }
+}
+
+impl Filter for Hysteresis {
+    fn update(&mut self, x: i16) -> i16 {
+        let y = match self.output {
+            Some(y) if (x as i32 - y as i32).abs() <= self.threshold as i32 => y,
+            _ => x,
+        };
+        self.output = Some(y);
+        y
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterKind {
+    MovingAverage(usize),
+    Median(usize),
+    LowPass(f32),
+    Hysteresis(i16),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterSpec {
+    pub channel: usize,
+    pub kind: FilterKind,
+}
+
+impl FilterSpec {
+    pub fn build(&self) -> Box<dyn Filter> {
+        match self.kind {
+            FilterKind::MovingAverage(n) => Box::new(MovingAverage::new(n)),
+            FilterKind::Median(n) => Box::new(Median::new(n)),
+            FilterKind::LowPass(alpha) => Box::new(LowPass::new(alpha)),
+            FilterKind::Hysteresis(threshold) => Box::new(Hysteresis::new(threshold)),
+        }
+    }
+}
+
+// format: <channel>:<avg|median|lowpass|hysteresis>:<param>, e.g. 0:median:5
+pub fn parse_filter_spec(s: &str) -> Result<FilterSpec, String> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() != 3 {
+        return Err(format!("{} should be <channel>:<kind>:<param>", s));
+    }
+    let channel = parts[0]
+        .parse::<usize>()
+        .map_err(|_| format!("invalid channel:{}", parts[0]))?;
+    let param = parts[2];
+    let param_err = |_| format!("invalid parameter of {}:{}", parts[1], param);
+    let kind = match parts[1] {
+        "avg" => FilterKind::MovingAverage(param.parse().map_err(param_err)?),
+        "median" => FilterKind::Median(param.parse().map_err(param_err)?),
+        "lowpass" => {
+            let alpha: f32 = param.parse().map_err(|_| format!("invalid parameter of lowpass:{}", param))?;
+            if !(0.0..=1.0).contains(&alpha) {
+                return Err(format!("lowpass alpha should be in 0~1, got {}", alpha));
+            }
+            FilterKind::LowPass(alpha)
+        }
+        "hysteresis" => FilterKind::Hysteresis(param.parse().map_err(param_err)?),
+        _ => return Err(format!("unknown filter:{}", parts[1])),
+    };
+    Ok(FilterSpec { channel, kind })
+}
+
+// filters of each channel are applied in the order they are given
+pub struct ChannelFilters {
+    filters: Vec<Vec<Box<dyn Filter>>>,
+}
+
+impl ChannelFilters {
+    pub fn new(channel_num: usize, specs: &[FilterSpec]) -> Self {
+        let mut filters: Vec<Vec<Box<dyn Filter>>> = (0..channel_num).map(|_| Vec::new()).collect();
+        for spec in specs {
+            if let Some(chain) = filters.get_mut(spec.channel) {
+                chain.push(spec.build());
+            }
+        }
+        ChannelFilters { filters }
+    }
+
+    pub fn apply(&mut self, value: &mut [i16]) {
+        for (v, chain) in value.iter_mut().zip(self.filters.iter_mut()) {
+            for f in chain.iter_mut() {
+                *v = f.update(*v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    const CENTER: i16 = 1000;
+
+    fn noisy_sequence(len: usize, noise: i16) -> Vec<i16> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..len)
+            .map(|_| CENTER + rng.gen_range(-noise..=noise))
+            .collect()
+    }
+
+    fn peak_to_peak(values: &[i16]) -> i16 {
+        values.iter().max().unwrap() - values.iter().min().unwrap()
+    }
+
+    // mean absolute deviation from CENTER
+    fn jitter(values: &[i16]) -> f32 {
+        values.iter().map(|x| (x - CENTER).abs() as f32).sum::<f32>() / values.len() as f32
+    }
+
+    fn run(filter: &mut dyn Filter, input: &[i16]) -> Vec<i16> {
+        input.iter().map(|x| filter.update(*x)).collect()
+    }
+
+    // samples needed to reach 90% of a step from CENTER to CENTER + 1000
+    fn step_latency(filter: &mut dyn Filter) -> usize {
+        for _ in 0..50 {
+            filter.update(CENTER);
+        }
+        (1..100)
+            .find(|_| filter.update(CENTER + 1000) >= CENTER + 900)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_filters_reduce_jitter() {
+        let input = noisy_sequence(500, 20);
+        let raw = jitter(&input[50..]);
+
+        let out = run(&mut MovingAverage::new(8), &input);
+        assert!(jitter(&out[50..]) < raw / 2.0);
+
+        let out = run(&mut Median::new(5), &input);
+        assert!(jitter(&out[50..]) < raw);
+
+        let out = run(&mut LowPass::new(0.1), &input);
+        assert!(jitter(&out[50..]) < raw / 2.0);
+
+        let out = run(&mut Hysteresis::new(40), &input);
+        assert!(peak_to_peak(&out[50..]) <= 40);
+    }
+
+    #[test]
+    fn test_median_rejects_spikes() {
+        let mut input = vec![CENTER; 100];
+        for i in (10..100).step_by(10) {
+            input[i] = i16::MAX;
+        }
+        let out = run(&mut Median::new(3), &input);
+        assert!(out.iter().all(|x| *x == CENTER));
+    }
+
+    #[test]
+    fn test_filter_latency() {
+        assert_eq!(step_latency(&mut Hysteresis::new(40)), 1);
+        assert_eq!(step_latency(&mut Median::new(5)), 3);
+        assert_eq!(step_latency(&mut MovingAverage::new(8)), 8);
+        let lowpass = step_latency(&mut LowPass::new(0.1));
+        assert!(lowpass > 8 && lowpass < 30);
+    }
+
+    #[test]
+    fn test_parse_filter_spec() {
+        assert_eq!(
+            parse_filter_spec("2:median:5").unwrap(),
+            FilterSpec { channel: 2, kind: FilterKind::Median(5) }
+        );
+        assert_eq!(
+            parse_filter_spec("0:lowpass:0.25").unwrap(),
+            FilterSpec { channel: 0, kind: FilterKind::LowPass(0.25) }
+        );
+        assert!(parse_filter_spec("0:lowpass:2").is_err());
+        assert!(parse_filter_spec("0:unknown:2").is_err());
+        assert!(parse_filter_spec("x:avg:2").is_err());
+        assert!(parse_filter_spec("0:avg").is_err());
+    }
+
+    #[test]
+    fn test_channel_filters() {
+        let specs = [
+            parse_filter_spec("0:hysteresis:10").unwrap(),
+            parse_filter_spec("1:avg:2").unwrap(),
+            parse_filter_spec("1:hysteresis:100").unwrap(),
+        ];
+        let mut filters = ChannelFilters::new(3, &specs);
+        let mut value = [100, 100, 100];
+        filters.apply(&mut value);
+        assert_eq!(value, [100, 100, 100]);
+
+        let mut value = [105, 300, 300];
+        filters.apply(&mut value);
+        // channel 1: avg gives 200, within hysteresis of 100
+        assert_eq!(value, [100, 100, 300]);
+    }
+}
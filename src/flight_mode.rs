@@ -0,0 +1,343 @@
+use std::{collections::BTreeMap, time::Instant};
+
+use crate::{
+    model::{MixInputs, Model, Rates, SwitchCondition, Switches, FULL_SCALE, MIXER_CHANNEL_NUM},
+};
+
+// longest fade, ms
+pub const FADE_MAX_MS: u32 = 15000;
+// weight of a fully active mode
+const FADE_FULL: i64 = 1_000_000;
+
+// model file, e.g.
+// [[flight_modes]]
+// name = "launch"
+// switch = "sw3:2"
+// fade_in = 300
+// fade_out = 500
+// disable_mix = ["aileron_diff"]
+// enable_mix = ["launch_camber"]
+// trims = { Elevator = 400 }
+// [[flight_modes.rates]]
+// input = "Elevator"
+// sets = [{ rate = 60, expo = 20 }]
+// the first mode whose switch is active wins, the model itself is used when none is
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct FlightMode {
+    pub name: String,
+    pub switch: SwitchCondition,
+    // ms to blend the outputs into this mode, and out of it
+    #[serde(default)]
+    pub fade_in: u32,
+    #[serde(default)]
+    pub fade_out: u32,
+    // replace the rates of the model for their inputs
+    #[serde(default)]
+    pub rates: Vec<Rates>,
+    // fixed trims by input name, replace the saved trims
+    #[serde(default)]
+    pub trims: BTreeMap<String, i32>,
+    // names of mix lines
+    #[serde(default)]
+    pub enable_mix: Vec<String>,
+    #[serde(default)]
+    pub disable_mix: Vec<String>,
+}
+
+impl FlightMode {
+    pub fn trim(&self, input: &str) -> Option<i32> {
+        self.trims
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(input))
+            .map(|(_, v)| *v)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.fade_in > FADE_MAX_MS || self.fade_out > FADE_MAX_MS {
+            return Err(format!(
+                "flight mode {}: fade_in {} and fade_out {} should be 0 ~ {} ms",
+                self.name, self.fade_in, self.fade_out, FADE_MAX_MS
+            ));
+        }
+        if let Some((input, trim)) = self.trims.iter().find(|(_, v)| !(-FULL_SCALE..=FULL_SCALE).contains(*v)) {
+            return Err(format!("flight mode {}: trim {} of {} should be -{} ~ {}", self.name, trim, input, FULL_SCALE, FULL_SCALE));
+        }
+        Ok(())
+    }
+}
+
+// 0: no flight mode active, n: the nth [[flight_modes]]
+pub fn select_flight_mode(modes: &[FlightMode], switches: &Switches) -> usize {
+    modes.iter().position(|x| x.switch.is_active(switches)).map_or(0, |i| i + 1)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FlightModeMsg {
+    // see select_flight_mode
+    pub index: u8,
+    // percent of the outputs coming from the active mode, 100 once the fade is over
+    pub fade: u8,
+}
+
+// blends the outputs of the modes while they fade in and out
+pub struct FlightModeFader {
+    // by mode index, 0 ~ FADE_FULL
+    weights: Vec<i64>,
+    active: usize,
+    // the mode active before `active`, it covers what the active mode hasn't faded in yet
+    previous: usize,
+    last: Option<Instant>,
+}
+
+impl FlightModeFader {
+    pub fn new(model: &Model) -> Self {
+        FlightModeFader {
+            weights: vec![0; model.flight_modes.len() + 1],
+            active: 0,
+            previous: 0,
+            last: None,
+        }
+    }
+
+    pub fn update(&mut self, model: &Model, switches: &Switches, now: Instant) -> FlightModeMsg {
+        let active = select_flight_mode(&model.flight_modes, switches);
+        if active != self.active {
+            self.previous = self.active;
+            self.active = active;
+        }
+        match self.last {
+            None => self.weights[self.active] = FADE_FULL,
+            Some(last) => {
+                let dt = now.duration_since(last).as_micros() as i64;
+                for (i, weight) in self.weights.iter_mut().enumerate() {
+                    let fade = |f: fn(&FlightMode) -> u32| if i == 0 { 0 } else { f(&model.flight_modes[i - 1]) };
+                    if i == self.active {
+                        *weight = (*weight + fade_step(fade(|x| x.fade_in), dt)).min(FADE_FULL);
+                    } else {
+                        *weight = (*weight - fade_step(fade(|x| x.fade_out), dt)).max(0);
+                    }
+                }
+                // a mode leaving faster than the next one fades in is held at the rest,
+                // so the fade in isn't lost by scaling the outputs back to full
+                let missing = FADE_FULL - self.weights.iter().sum::<i64>();
+                if missing > 0 && self.previous != self.active {
+                    self.weights[self.previous] += missing;
+                }
+            }
+        }
+        self.last = Some(now);
+        FlightModeMsg {
+            index: self.active as u8,
+            fade: (self.weights[self.active] * 100 / self.total()) as u8,
+        }
+    }
+
+    // FADE_FULL or more, more while modes fade out slower than the active one fades in
+    fn total(&self) -> i64 {
+        self.weights.iter().sum::<i64>().max(1)
+    }
+
+    // mixes every mode with some weight left
+    pub fn mix(&self, model: &Model, inputs: &MixInputs) -> [u16; MIXER_CHANNEL_NUM] {
+        if self.weights.iter().enumerate().all(|(i, w)| i == self.active || *w == 0) {
+            return model.mix(&MixInputs {
+                flight_mode: self.active,
+                ..*inputs
+            });
+        }
+        let total = self.total();
+        let mut sums = [0i64; MIXER_CHANNEL_NUM];
+        for (i, weight) in self.weights.iter().enumerate().filter(|(_, w)| **w > 0) {
+            let out = model.mix(&MixInputs {
+                flight_mode: i,
+                ..*inputs
+            });
+            for (sum, x) in sums.iter_mut().zip(out) {
+                *sum += x as i64 * weight;
+            }
+        }
+        sums.map(|x| (x / total) as u16)
+    }
+}
+
+// weight change in `dt` us for a fade of `fade` ms
+fn fade_step(fade: u32, dt: i64) -> i64 {
+    if fade == 0 {
+        FADE_FULL
+    } else {
+        dt * 1000 / fade as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::trim::Trims;
+
+    fn model() -> Model {
+        toml::from_str::<Model>(
+            r#"
+            [[mix]]
+            channel = 1
+            source = "Aileron"
+
+            [[mix]]
+            name = "camber"
+            channel = 2
+            source = "max"
+            weight = 50
+            disabled = true
+
+            [[mix]]
+            name = "elevator"
+            channel = 3
+            source = "Elevator"
+
+            [[rates]]
+            input = "Aileron"
+            sets = [{ rate = 50 }]
+
+            [[trims]]
+            input = "Elevator"
+
+            [[flight_modes]]
+            name = "launch"
+            switch = "sw1:1"
+            fade_in = 100
+            fade_out = 200
+            enable_mix = ["camber"]
+            trims = { elevator = 1000 }
+            [[flight_modes.rates]]
+            input = "Aileron"
+            sets = [{ rate = 100 }]
+
+            [[flight_modes]]
+            name = "landing"
+            switch = "sw2:1"
+            disable_mix = ["elevator"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn switches(sw1: u8, sw2: u8) -> Switches {
+        let mut sw = Switches::default();
+        sw.physical.value[0] = sw1;
+        sw.physical.value[1] = sw2;
+        sw
+    }
+
+    fn mix(model: &Model, fader: &FlightModeFader, sw: &Switches, trims: &Trims) -> [u16; 3] {
+        let inputs = [("Aileron", FULL_SCALE), ("Elevator", 0)];
+        let out = fader.mix(
+            model,
+            &MixInputs {
+                inputs: &inputs,
+                switches: sw,
+                trims,
+                flight_mode: 0,
+            },
+        );
+        [out[0], out[1], out[2]]
+    }
+
+    #[test]
+    fn test_flight_mode_overrides() {
+        let model = model();
+        assert!(model.validate().is_ok());
+        let mut trims = Trims::default();
+        trims.step(&model.trims[0], 10);
+        let mut fader = FlightModeFader::new(&model);
+        let now = Instant::now();
+
+        let sw = switches(0, 0);
+        assert_eq!(fader.update(&model, &sw, now), FlightModeMsg { index: 0, fade: 100 });
+        // saved trim, camber disabled: centered like a line switched off
+        assert_eq!(mix(&model, &fader, &sw, &trims), [7500, 5000, 5200]);
+
+        // the first active mode wins
+        let sw = switches(1, 1);
+        let mut fader = FlightModeFader::new(&model);
+        assert_eq!(fader.update(&model, &sw, now).index, 1);
+        assert_eq!(mix(&model, &fader, &sw, &trims), [10000, 7500, 6000]);
+
+        let sw = switches(0, 1);
+        let mut fader = FlightModeFader::new(&model);
+        assert_eq!(fader.update(&model, &sw, now).index, 2);
+        assert_eq!(mix(&model, &fader, &sw, &trims), [7500, 5000, 5000]);
+    }
+
+    #[test]
+    fn test_flight_mode_fade() {
+        let model = model();
+        let trims = Trims::default();
+        let mut fader = FlightModeFader::new(&model);
+        let mut now = Instant::now();
+        let ms = Duration::from_millis(1);
+        fader.update(&model, &switches(0, 0), now);
+
+        // into launch over 100ms, the model has no fade out and covers the rest meanwhile: 20% launch + 80% model
+        let sw = switches(1, 0);
+        now += 20 * ms;
+        assert_eq!(fader.update(&model, &sw, now), FlightModeMsg { index: 1, fade: 20 });
+        assert_eq!(mix(&model, &fader, &sw, &trims), [8000, 5500, 5200]);
+        now += 50 * ms;
+        assert_eq!(fader.update(&model, &sw, now), FlightModeMsg { index: 1, fade: 70 });
+        assert_eq!(mix(&model, &fader, &sw, &trims)[0], 9250);
+        now += 50 * ms;
+        fader.update(&model, &sw, now);
+        assert_eq!(fader.weights[1], FADE_FULL);
+
+        // back to no mode: launch fades out over 200ms
+        let sw = switches(0, 0);
+        for step in 1..=4 {
+            now += 50 * ms;
+            let msg = fader.update(&model, &sw, now);
+            // launch weight in 1/200, the model is back at full weight
+            let launch = 200 - step * 50;
+            let expected = (10000 * launch + 7500 * 200) / (launch + 200);
+            assert_eq!(msg, FlightModeMsg { index: 0, fade: (100 * 200 / (launch + 200)) as u8 });
+            assert_eq!(mix(&model, &fader, &sw, &trims)[0], expected as u16);
+        }
+        assert_eq!(mix(&model, &fader, &sw, &trims), [7500, 5000, 5000]);
+
+        // halfway into launch, then landing: launch fades back out from where it was
+        now += 50 * ms;
+        fader.update(&model, &switches(1, 0), now);
+        assert_eq!(fader.weights[1], FADE_FULL / 2);
+        assert_eq!(fader.weights[0], FADE_FULL / 2);
+        now += 100 * ms;
+        let msg = fader.update(&model, &switches(0, 1), now);
+        assert_eq!(msg.index, 2);
+        assert_eq!(fader.weights[..2], [0, 0]);
+        assert_eq!(msg.fade, 100);
+    }
+
+    #[test]
+    fn test_validate_flight_modes() {
+        let mut model = model();
+        model.flight_modes[0].fade_in = FADE_MAX_MS + 1;
+        assert!(model.validate().is_err());
+
+        let mut model = self::model();
+        model.flight_modes[0].trims.insert("Aileron".to_string(), 6000);
+        assert!(model.validate().is_err());
+
+        let mut model = self::model();
+        model.flight_modes[1].disable_mix.push("flaps".to_string());
+        assert!(model.validate().is_err());
+
+        let mut model = self::model();
+        model.flight_modes[1].name = "Launch".to_string();
+        assert!(model.validate().is_err());
+
+        let mut model = self::model();
+        model.flight_modes[0].rates[0].sets.clear();
+        assert!(model.validate().is_err());
+
+        let model = self::model();
+        assert_eq!(model.input_names(), ["Aileron", "Elevator", "Aileron", "Aileron", "Elevator", "elevator"]);
+    }
+}
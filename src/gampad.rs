@@ -36,7 +36,9 @@ fn gamepad_main(_argc: u32, _argv: *const &str) {
     let mut rx = mixer_out_subscriber();
     loop{
         let mix_out = rx.read();
-        game_pad.update_report(0, &[mix_out.thrust,mix_out.direction,mix_out.aileron,mix_out.elevator]);
+        // channels 1~4 are AETR in the default model, reported as thrust, direction, aileron, elevator
+        let ch = mix_out.channels;
+        game_pad.update_report(0, &[ch[2],ch[3],ch[0],ch[1]]);
     }
     
 }
@@ -0,0 +1,246 @@
+use std::time::Duration;
+
+use clap::Parser;
+use gpio_cdev::{Chip, LineRequestFlags, MultiLineHandle};
+use rpos::thread_logln;
+
+use crate::{client_process_args, msgbus::switch_state_publisher};
+
+pub const SWITCH_NUM: usize = 8;
+
+// 2-position switches report 0 or 1, 3-position switches report 0(up), 1(mid) or 2(down)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SwitchStateMsg {
+    pub value: [u8; SWITCH_NUM],
+    // 2 or 3, 0 for unused switches
+    pub positions: [u8; SWITCH_NUM],
+}
+
+#[derive(Parser)]
+#[command(name="gpio_switch", about = "used for switches and buttons wired to gpio(/dev/gpiochip*)", long_about = None)]
+struct Cli {
+    #[arg(short, long, default_value = "switch.toml")]
+    config: String,
+}
+
+fn default_poll_ms() -> u64 {
+    5
+}
+
+fn default_debounce_ms() -> u64 {
+    20
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SwitchConfig {
+    name: String,
+    // one line for a 2-position switch, two lines(up, down) for a 3-position switch
+    lines: Vec<u32>,
+    #[serde(default)]
+    active_low: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct GpioSwitchConfig {
+    chip: String,
+    #[serde(default = "default_poll_ms")]
+    poll_ms: u64,
+    #[serde(default = "default_debounce_ms")]
+    debounce_ms: u64,
+    switches: Vec<SwitchConfig>,
+}
+
+struct Debouncer {
+    stable: bool,
+    candidate: bool,
+    count: u32,
+    threshold: u32,
+}
+
+impl Debouncer {
+    fn new(threshold: u32) -> Self {
+        Debouncer {
+            stable: false,
+            candidate: false,
+            count: 0,
+            threshold,
+        }
+    }
+
+    // a new level is accepted only after it has been sampled `threshold` times in a row
+    fn update(&mut self, raw: bool) -> bool {
+        if raw == self.stable {
+            self.count = 0;
+            return self.stable;
+        }
+
+        if raw != self.candidate {
+            self.candidate = raw;
+            self.count = 0;
+        }
+        self.count += 1;
+        if self.count >= self.threshold {
+            self.stable = raw;
+            self.count = 0;
+        }
+        self.stable
+    }
+}
+
+fn switch_position(lines: &[bool]) -> u8 {
+    match lines {
+        [active] => *active as u8,
+        [true, false] => 0,
+        [false, true] => 2,
+        // neither or both lines active: treat as middle
+        _ => 1,
+    }
+}
+
+struct GpioSwitch {
+    positions: u8,
+    handle: MultiLineHandle,
+    debouncers: Vec<Debouncer>,
+}
+
+impl GpioSwitch {
+    fn read(&mut self) -> Result<u8, gpio_cdev::Error> {
+        let values = self.handle.get_values()?;
+        let lines: Vec<bool> = values
+            .iter()
+            .zip(self.debouncers.iter_mut())
+            .map(|(v, d)| d.update(*v != 0))
+            .collect();
+        Ok(switch_position(&lines))
+    }
+}
+
+fn gpio_switch_main(argc: u32, argv: *const &str) {
+    let ret = client_process_args::<Cli>(argc, argv);
+    if ret.is_none() {
+        return;
+    }
+
+    let args = ret.unwrap();
+    let toml_str = match std::fs::read_to_string(&args.config) {
+        Ok(s) => s,
+        Err(_) => {
+            thread_logln!("no {} found. please configure switches first!", args.config);
+            return;
+        }
+    };
+    let config = match toml::from_str::<GpioSwitchConfig>(toml_str.as_str()) {
+        Ok(config) => config,
+        Err(e) => {
+            thread_logln!("failed to parse {}: {}", args.config, e);
+            return;
+        }
+    };
+    if config.switches.len() > SWITCH_NUM {
+        thread_logln!("too many switches, only {} supported.", SWITCH_NUM);
+        return;
+    }
+    if config.poll_ms == 0 {
+        thread_logln!("poll_ms should be greater than 0.");
+        return;
+    }
+
+    let mut chip = match Chip::new(&config.chip) {
+        Ok(chip) => chip,
+        Err(e) => {
+            thread_logln!("failed to open {}: {}", config.chip, e);
+            return;
+        }
+    };
+    let threshold = (config.debounce_ms / config.poll_ms).max(1) as u32;
+    let mut switches = Vec::new();
+    for sw in &config.switches {
+        if sw.lines.is_empty() || sw.lines.len() > 2 {
+            thread_logln!("switch {} should have 1 or 2 lines.", sw.name);
+            return;
+        }
+        let mut flags = LineRequestFlags::INPUT;
+        if sw.active_low {
+            flags |= LineRequestFlags::ACTIVE_LOW;
+        }
+        let handle = match chip
+            .get_lines(&sw.lines)
+            .and_then(|lines| lines.request(flags, &vec![0; sw.lines.len()], "LinTx"))
+        {
+            Ok(handle) => handle,
+            Err(e) => {
+                thread_logln!("failed to request lines {:?} of switch {}: {}", sw.lines, sw.name, e);
+                return;
+            }
+        };
+        switches.push(GpioSwitch {
+            positions: sw.lines.len() as u8 + 1,
+            handle,
+            debouncers: sw.lines.iter().map(|_| Debouncer::new(threshold)).collect(),
+        });
+    }
+
+    let tx = switch_state_publisher();
+    let mut last = None;
+
+    thread_logln!("gpio_switch started with {} switches!", switches.len());
+
+    loop {
+        let mut msg = SwitchStateMsg::default();
+        for (index, sw) in switches.iter_mut().enumerate() {
+            msg.value[index] = match sw.read() {
+                Ok(position) => position,
+                Err(e) => {
+                    thread_logln!("failed to read switch {}: {}", config.switches[index].name, e);
+                    return;
+                }
+            };
+            msg.positions[index] = sw.positions;
+        }
+
+        if last != Some(msg.value) {
+            tx.publish(msg);
+            last = Some(msg.value);
+        }
+        std::thread::sleep(Duration::from_millis(config.poll_ms));
+    }
+}
+
+#[rpos::ctor::ctor]
+fn register() {
+    rpos::module::Module::register("gpio_switch", gpio_switch_main);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debouncer() {
+        let mut d = Debouncer::new(3);
+        assert!(!d.update(true));
+        assert!(!d.update(false));
+        assert!(!d.update(true));
+        assert!(!d.update(true));
+        assert!(d.update(true));
+
+        // a single glitch is ignored
+        assert!(d.update(false));
+        assert!(d.update(true));
+        assert!(d.update(true));
+
+        let mut d = Debouncer::new(1);
+        assert!(d.update(true));
+        assert!(!d.update(false));
+    }
+
+    #[test]
+    fn test_switch_position() {
+        assert_eq!(switch_position(&[false]), 0);
+        assert_eq!(switch_position(&[true]), 1);
+        assert_eq!(switch_position(&[true, false]), 0);
+        assert_eq!(switch_position(&[false, false]), 1);
+        assert_eq!(switch_position(&[false, true]), 2);
+        assert_eq!(switch_position(&[true, true]), 1);
+    }
+}
@@ -2,15 +2,155 @@ use std::collections::HashMap;
 
 use clap::Parser;
 use joydev::{event_codes::AbsoluteAxis, GenericEvent};
+use rpos::thread_logln;
 
-use crate::{adc::AdcRawMsg, client_process_args, msgbus::adc_raw_publisher};
+use crate::{
+    adc::{AdcRawMsg, AdcSource, ADC_CHANNEL_NUM},
+    client_process_args,
+    device::{Backoff, DeviceStatus},
+    ev_dev::ButtonMsg,
+    msgbus::{adc_raw_publisher, button_publisher},
+};
 
 #[derive(Parser)]
 #[command(name="joy_dev", about = "used for machine with joysticks(/dev/input/js*)", long_about = None)]
 struct Cli {
+    /// built-in profile name(default, steamdeck, flightstick) or path of a profile file.
+    #[arg(short, long, default_value = "default")]
+    profile: String,
+
     dev_name: String,
 }
 
+// axes are keyed by joydev axis names, buttons by the js button number
+const BUILTIN_PROFILES: &[(&str, &str)] = &[
+    (
+        "default",
+        r#"
+        [axes]
+        LeftX = 0
+        LeftY = 1
+        RightX = 2
+        RightY = 3
+        "#,
+    ),
+    (
+        "steamdeck",
+        r#"
+        [axes]
+        LeftX = 0
+        LeftY = 1
+        RightX = 2
+        RightY = 3
+        LeftZ = 4
+        RightZ = 5
+        [buttons]
+        0 = 0
+        1 = 1
+        2 = 2
+        3 = 3
+        4 = 4
+        5 = 5
+        "#,
+    ),
+    (
+        "flightstick",
+        r#"
+        [axes]
+        LeftX = 0
+        LeftY = 1
+        Throttle = 2
+        RightZ = 3
+        [buttons]
+        0 = 0
+        1 = 1
+        "#,
+    ),
+];
+
+const AXIS_NAMES: &[(&str, AbsoluteAxis)] = &[
+    ("LeftX", AbsoluteAxis::LeftX),
+    ("LeftY", AbsoluteAxis::LeftY),
+    ("LeftZ", AbsoluteAxis::LeftZ),
+    ("RightX", AbsoluteAxis::RightX),
+    ("RightY", AbsoluteAxis::RightY),
+    ("RightZ", AbsoluteAxis::RightZ),
+    ("Throttle", AbsoluteAxis::Throttle),
+    ("Rudder", AbsoluteAxis::Rudder),
+    ("Wheel", AbsoluteAxis::Wheel),
+    ("Gas", AbsoluteAxis::Gas),
+    ("Brake", AbsoluteAxis::Brake),
+    ("Hat0X", AbsoluteAxis::Hat0X),
+    ("Hat0Y", AbsoluteAxis::Hat0Y),
+    ("Hat1X", AbsoluteAxis::Hat1X),
+    ("Hat1Y", AbsoluteAxis::Hat1Y),
+];
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct JoyProfile {
+    #[serde(default)]
+    axes: HashMap<String, usize>,
+    #[serde(default)]
+    buttons: HashMap<String, usize>,
+}
+
+struct JoyMap {
+    axes: HashMap<AbsoluteAxis, usize>,
+    buttons: HashMap<u8, usize>,
+    channel_num: usize,
+}
+
+impl JoyMap {
+    fn from_profile(profile: &JoyProfile) -> Result<Self, String> {
+        let mut axes = HashMap::new();
+        for (name, index) in &profile.axes {
+            let axis = AXIS_NAMES
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, a)| *a)
+                .ok_or(format!("unknown axis:{}", name))?;
+            if *index >= ADC_CHANNEL_NUM {
+                return Err(format!("axis {} mapped to channel {}, only {} channels available", name, index, ADC_CHANNEL_NUM));
+            }
+            axes.insert(axis, *index);
+        }
+
+        let mut buttons = HashMap::new();
+        for (number, index) in &profile.buttons {
+            let number = number
+                .parse::<u8>()
+                .map_err(|_| format!("invalid button number:{}", number))?;
+            if *index >= u32::BITS as usize {
+                return Err(format!("button {} mapped to {}, only {} buttons available", number, index, u32::BITS));
+            }
+            buttons.insert(number, *index);
+        }
+
+        let channel_num = axes.values().max().map_or(0, |x| x + 1);
+        Ok(JoyMap {
+            axes,
+            buttons,
+            channel_num,
+        })
+    }
+}
+
+fn load_profile(name: &str) -> Result<JoyProfile, String> {
+    let toml_str = match BUILTIN_PROFILES.iter().find(|(n, _)| *n == name) {
+        Some((_, s)) => s.to_string(),
+        None => std::fs::read_to_string(name).map_err(|e| format!("{}: {}", name, e))?,
+    };
+    toml::from_str::<JoyProfile>(toml_str.as_str()).map_err(|e| e.to_string())
+}
+
+fn open_device(dev_name: &str) -> Result<joydev::Device, String> {
+    let file = std::fs::File::options()
+        .read(true)
+        .open(dev_name)
+        .map_err(|e| e.to_string())?;
+    joydev::Device::new(file).map_err(|e| format!("{:?}", e))
+}
+
 fn joy_dev_main(argc: u32, argv: *const &str) {
     let ret = client_process_args::<Cli>(argc, argv);
     if ret.is_none() {
@@ -18,33 +158,66 @@ fn joy_dev_main(argc: u32, argv: *const &str) {
     }
 
     let args = ret.unwrap();
-    let file = std::fs::File::options()
-        .read(true)
-        .open(args.dev_name)
-        .unwrap();
-    let dev = joydev::Device::new(file).unwrap();
+    let map = match load_profile(&args.profile).and_then(|p| JoyMap::from_profile(&p)) {
+        Ok(m) => m,
+        Err(e) => {
+            thread_logln!("failed to load joy_dev profile {}: {}", args.profile, e);
+            return;
+        }
+    };
 
     let adc_raw_tx = adc_raw_publisher();
-    let chn_map: HashMap<AbsoluteAxis, usize> = [
-        (AbsoluteAxis::LeftX, 0),
-        (AbsoluteAxis::LeftY, 1),
-        (AbsoluteAxis::RightX, 2),
-        (AbsoluteAxis::RightY, 3),
-    ]
-    .into_iter()
-    .collect();
-    let mut chn_value: [i16; 4] = [0; 4];
+    let button_tx = button_publisher();
+    let mut chn_value: Vec<i16> = vec![0; map.channel_num];
+    let mut buttons = ButtonMsg::default();
+    let mut status = DeviceStatus::new("joy_dev", &args.dev_name);
+    let mut backoff = Backoff::new();
+    let mut dev = None;
 
     loop {
-        let s = dev.get_event().unwrap();
+        if dev.is_none() {
+            match open_device(&args.dev_name) {
+                Ok(d) => {
+                    dev = Some(d);
+                    status.set(true);
+                    backoff.reset();
+                }
+                Err(_) => {
+                    status.set(false);
+                    std::thread::sleep(backoff.next_delay());
+                    continue;
+                }
+            }
+        }
+
+        let s = match dev.as_ref().unwrap().get_event() {
+            Ok(s) => s,
+            Err(_) => {
+                // device unplugged, release the buttons and wait for it to come back
+                dev = None;
+                status.set(false);
+                buttons = ButtonMsg::default();
+                button_tx.publish(buttons);
+                continue;
+            }
+        };
         match s {
             joydev::DeviceEvent::Axis(x) => {
-                if let Some(index) = chn_map.get(&x.axis()) {
+                if let Some(index) = map.axes.get(&x.axis()) {
                     chn_value[*index] = x.value();
-                    adc_raw_tx.publish(AdcRawMsg { value: chn_value });
+                    adc_raw_tx.publish(AdcRawMsg::new(AdcSource::JoyDev, &chn_value));
+                }
+            }
+            joydev::DeviceEvent::Button(x) => {
+                if let Some(index) = map.buttons.get(&x.number()) {
+                    if x.value() != 0 {
+                        buttons.value |= 1 << index;
+                    } else {
+                        buttons.value &= !(1 << index);
+                    }
+                    button_tx.publish(buttons);
                 }
             }
-            _ => {}
         }
     }
 }
@@ -53,3 +226,38 @@ fn joy_dev_main(argc: u32, argv: *const &str) {
 fn register() {
     rpos::module::Module::register("joy_dev", joy_dev_main);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_profiles() {
+        for (name, _) in BUILTIN_PROFILES {
+            let map = JoyMap::from_profile(&load_profile(name).unwrap()).unwrap();
+            assert!(map.channel_num >= 4);
+        }
+
+        let map = JoyMap::from_profile(&load_profile("steamdeck").unwrap()).unwrap();
+        assert_eq!(map.channel_num, 6);
+        assert_eq!(map.axes[&AbsoluteAxis::RightZ], 5);
+        assert_eq!(map.buttons[&3], 3);
+    }
+
+    #[test]
+    fn test_invalid_profile() {
+        let mut profile = JoyProfile::default();
+        profile.axes.insert("NotAnAxis".to_string(), 0);
+        assert!(JoyMap::from_profile(&profile).is_err());
+
+        let mut profile = JoyProfile::default();
+        profile.axes.insert("LeftX".to_string(), ADC_CHANNEL_NUM);
+        assert!(JoyMap::from_profile(&profile).is_err());
+
+        let mut profile = JoyProfile::default();
+        profile.buttons.insert("x".to_string(), 0);
+        assert!(JoyMap::from_profile(&profile).is_err());
+
+        assert!(load_profile("/not/exist/profile.toml").is_err());
+    }
+}
@@ -1,13 +1,12 @@
 use rpos::thread_log;
 
-use crate::{mixer::MixerOutMsg, msgbus::mixer_out_subscriber};
+use crate::{mixer::MixerOutMsg, model::MIXER_CHANNEL_NUM, msgbus::mixer_out_subscriber};
 
 fn channel_out(mixout:&MixerOutMsg){
-    thread_log!("\x1b[2KThrust:{}\n",mixout.thrust);
-    thread_log!("\x1b[2Kdirection:{}\n",mixout.direction);
-    thread_log!("\x1b[2Kaileron:{}\n",mixout.aileron);
-    thread_log!("\x1b[2Kelevator:{}\n",mixout.elevator);
-    thread_log!("\x1b[4A");
+    for (index,value) in mixout.channels.iter().enumerate(){
+        thread_log!("\x1b[2Kch{}:{}\n",index+1,value);
+    }
+    thread_log!("\x1b[{}A",MIXER_CHANNEL_NUM);
     
 }
 fn joysticks_test_main(_argc: u32, _argv: *const &str) {
@@ -30,10 +29,7 @@ mod tests{
     fn test_channel_out(){
         for i in 0..100 as u16{
             let mixout = MixerOutMsg{
-                thrust:i*100,
-                direction:i*100,
-                elevator:i*100,
-                aileron:i*100,
+                channels:[i*100;MIXER_CHANNEL_NUM],
             };
             channel_out(&mixout);
             std::thread::sleep(std::time::Duration::from_secs(1));
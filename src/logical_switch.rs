@@ -0,0 +1,472 @@
+use std::time::Instant;
+
+use crate::model::{MixInputs, Source, SwitchCondition, SwitchId, FULL_SCALE};
+
+pub const LOGICAL_SWITCH_NUM: usize = 32;
+// longest timer, delay or duration, ms
+pub const LOGICAL_TIME_MAX_MS: u32 = 600_000;
+
+// model file, ls1 is the first one, e.g.
+// [[logical_switches]]
+// func = "compare"
+// a = "Thrust"
+// op = ">"
+// value = -60
+// [[logical_switches]]
+// func = "and"
+// conditions = ["sw1:0", "sw2:2"]
+// [[logical_switches]]
+// func = "sticky"
+// set = "btn3"
+// they are read like physical switches: "ls1" as a source, "ls1" or "ls1:0" as a condition
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct LogicalSwitch {
+    #[serde(flatten)]
+    pub function: LogicalFunction,
+    // also has to be on
+    #[serde(default)]
+    pub and: Option<SwitchCondition>,
+    // ms the function has to stay on before the switch turns on
+    #[serde(default)]
+    pub delay: u32,
+    // ms the switch stays on at most, 0 for as long as the function is on
+    #[serde(default)]
+    pub duration: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(tag = "func", rename_all = "kebab-case")]
+pub enum LogicalFunction {
+    // a <op> b + value, value in percent. b is 0 when omitted
+    Compare {
+        a: Source,
+        op: CompareOp,
+        #[serde(default)]
+        b: Option<Source>,
+        #[serde(default)]
+        value: i32,
+    },
+    And {
+        conditions: Vec<SwitchCondition>,
+    },
+    Or {
+        conditions: Vec<SwitchCondition>,
+    },
+    // on when an odd number of the conditions is
+    Xor {
+        conditions: Vec<SwitchCondition>,
+    },
+    // on for one cycle when `a` turns on, or off with falling
+    Edge {
+        a: SwitchCondition,
+        #[serde(default)]
+        falling: bool,
+    },
+    // turned on when `set` turns on and off when `reset` does
+    // without reset every turn on of `set` toggles it, e.g. a momentary button
+    Sticky {
+        set: SwitchCondition,
+        #[serde(default)]
+        reset: Option<SwitchCondition>,
+    },
+    // on for `on` ms then off for `off` ms, repeating
+    Timer {
+        on: u32,
+        off: u32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+pub enum CompareOp {
+    #[serde(rename = ">")]
+    Greater,
+    #[serde(rename = "<")]
+    Less,
+    // compares the absolute value of a
+    #[serde(rename = "|a|>")]
+    AbsGreater,
+    #[serde(rename = "|a|<")]
+    AbsLess,
+}
+
+impl LogicalSwitch {
+    pub fn validate(&self, count: usize) -> Result<(), String> {
+        let times = match self.function {
+            LogicalFunction::Timer { on, off } => vec![self.delay, self.duration, on, off],
+            _ => vec![self.delay, self.duration],
+        };
+        if times.iter().any(|x| *x > LOGICAL_TIME_MAX_MS) {
+            return Err(format!("times should be 0 ~ {} ms", LOGICAL_TIME_MAX_MS));
+        }
+        match &self.function {
+            LogicalFunction::Compare { value, .. } if !(-200..=200).contains(value) => {
+                return Err(format!("compare value {} should be -200 ~ 200", value));
+            }
+            LogicalFunction::And { conditions } | LogicalFunction::Or { conditions } | LogicalFunction::Xor { conditions }
+                if conditions.len() < 2 =>
+            {
+                return Err("needs at least 2 conditions".to_string());
+            }
+            LogicalFunction::Timer { on, off } if on + off == 0 => {
+                return Err("timer needs on or off time".to_string());
+            }
+            _ => {}
+        }
+        validate_logical_refs(&self.switches(), count)
+    }
+
+    // switches read by this one
+    pub fn switches(&self) -> Vec<SwitchId> {
+        let conditions: Vec<&SwitchCondition> = match &self.function {
+            LogicalFunction::And { conditions } | LogicalFunction::Or { conditions } | LogicalFunction::Xor { conditions } => {
+                conditions.iter().collect()
+            }
+            LogicalFunction::Edge { a, .. } => vec![a],
+            LogicalFunction::Sticky { set, reset } => [Some(set), reset.as_ref()].into_iter().flatten().collect(),
+            LogicalFunction::Compare { .. } | LogicalFunction::Timer { .. } => Vec::new(),
+        };
+        let sources = self.sources().into_iter().filter_map(|x| match x {
+            Source::Switch(id) => Some(*id),
+            _ => None,
+        });
+        conditions
+            .into_iter()
+            .chain(self.and.as_ref())
+            .map(|x| x.switch)
+            .chain(sources)
+            .collect()
+    }
+
+    // calibrated inputs read by compare
+    pub fn inputs(&self) -> Vec<&str> {
+        self.sources()
+            .into_iter()
+            .filter_map(|x| match x {
+                Source::Input(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn sources(&self) -> Vec<&Source> {
+        match &self.function {
+            LogicalFunction::Compare { a, b, .. } => [Some(a), b.as_ref()].into_iter().flatten().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// every logical switch in `switches` is one of the first `count`
+pub fn validate_logical_refs(switches: &[SwitchId], count: usize) -> Result<(), String> {
+    match switches.iter().find(|x| matches!(x, SwitchId::Logical(i) if *i >= count)) {
+        Some(SwitchId::Logical(i)) => Err(format!("ls{} is not defined", i + 1)),
+        _ => Ok(()),
+    }
+}
+
+// true when the value turned from false to true
+fn rising(last: &mut Option<bool>, value: bool) -> bool {
+    let rising = *last == Some(false) && value;
+    *last = Some(value);
+    rising
+}
+
+#[derive(Default)]
+struct LogicalState {
+    // of `a` or `set`
+    last: Option<bool>,
+    last_reset: Option<bool>,
+    sticky: bool,
+    // since the function and `and` are on
+    function_on: Option<Instant>,
+    // since the switch is on
+    switch_on: Option<Instant>,
+}
+
+impl LogicalState {
+    fn update(&mut self, config: &LogicalSwitch, inputs: &MixInputs, now: Instant, start: Instant) -> bool {
+        let active = |x: &SwitchCondition| x.is_active(inputs.switches);
+        let function = match &config.function {
+            LogicalFunction::Compare { a, op, b, value } => {
+                let a = inputs.value(a);
+                let b = b.as_ref().map_or(0, |x| inputs.value(x)) + value * FULL_SCALE / 100;
+                match op {
+                    CompareOp::Greater => a > b,
+                    CompareOp::Less => a < b,
+                    CompareOp::AbsGreater => a.abs() > b,
+                    CompareOp::AbsLess => a.abs() < b,
+                }
+            }
+            LogicalFunction::And { conditions } => conditions.iter().all(active),
+            LogicalFunction::Or { conditions } => conditions.iter().any(active),
+            LogicalFunction::Xor { conditions } => conditions.iter().filter(|x| active(x)).count() % 2 == 1,
+            LogicalFunction::Edge { a, falling } => rising(&mut self.last, active(a) != *falling),
+            LogicalFunction::Sticky { set, reset } => {
+                let set = rising(&mut self.last, active(set));
+                match reset {
+                    Some(reset) => {
+                        if rising(&mut self.last_reset, active(reset)) {
+                            self.sticky = false;
+                        } else if set {
+                            self.sticky = true;
+                        }
+                    }
+                    None => self.sticky ^= set,
+                }
+                self.sticky
+            }
+            LogicalFunction::Timer { on, off } => {
+                let period = (on + off) as u128;
+                now.duration_since(start).as_millis() % period < *on as u128
+            }
+        };
+
+        if !(function && config.and.is_none_or(|x| active(&x))) {
+            self.function_on = None;
+            self.switch_on = None;
+            return false;
+        }
+        let function_on = *self.function_on.get_or_insert(now);
+        if now.duration_since(function_on).as_millis() < config.delay as u128 {
+            return false;
+        }
+        let switch_on = *self.switch_on.get_or_insert(now);
+        config.duration == 0 || now.duration_since(switch_on).as_millis() < config.duration as u128
+    }
+}
+
+pub struct LogicalSwitches {
+    states: Vec<LogicalState>,
+    start: Option<Instant>,
+}
+
+impl LogicalSwitches {
+    pub fn new(configs: &[LogicalSwitch]) -> Self {
+        LogicalSwitches {
+            states: configs.iter().map(|_| LogicalState::default()).collect(),
+            start: None,
+        }
+    }
+
+    // in order, a switch sees the ones before it from this cycle and the ones after it from the last
+    pub fn update(&mut self, configs: &[LogicalSwitch], inputs: &MixInputs, now: Instant) -> [bool; LOGICAL_SWITCH_NUM] {
+        let start = *self.start.get_or_insert(now);
+        let mut switches = *inputs.switches;
+        for (i, (config, state)) in configs.iter().zip(self.states.iter_mut()).enumerate() {
+            let value = state.update(
+                config,
+                &MixInputs {
+                    switches: &switches,
+                    ..*inputs
+                },
+                now,
+                start,
+            );
+            switches.logical[i] = value;
+        }
+        switches.logical
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{model::Switches, trim::Trims};
+
+    fn parse(s: &str) -> LogicalSwitch {
+        toml::from_str::<LogicalSwitch>(s).unwrap()
+    }
+
+    // runs the switches on the given sticks (percent) and switches
+    struct Runner {
+        configs: Vec<LogicalSwitch>,
+        engine: LogicalSwitches,
+        switches: Switches,
+        now: Instant,
+    }
+
+    impl Runner {
+        fn new(configs: &[&str]) -> Self {
+            let configs: Vec<LogicalSwitch> = configs.iter().map(|x| parse(x)).collect();
+            for config in configs.iter() {
+                assert!(config.validate(configs.len()).is_ok(), "{:?}", config);
+            }
+            Runner {
+                engine: LogicalSwitches::new(&configs),
+                configs,
+                switches: Switches::default(),
+                now: Instant::now(),
+            }
+        }
+
+        fn run(&mut self, sticks: &[(&str, i32)], ms: u64) -> Vec<bool> {
+            self.now += Duration::from_millis(ms);
+            let inputs: Vec<(&str, i32)> = sticks.iter().map(|(name, v)| (*name, v * FULL_SCALE / 100)).collect();
+            let trims = Trims::default();
+            self.switches.logical = self.engine.update(
+                &self.configs,
+                &MixInputs {
+                    inputs: &inputs,
+                    switches: &self.switches,
+                    trims: &trims,
+                    flight_mode: 0,
+                },
+                self.now,
+            );
+            self.switches.logical[..self.configs.len()].to_vec()
+        }
+
+        fn switch(&mut self, sw: usize, position: u8) -> &mut Self {
+            self.switches.physical.value[sw - 1] = position;
+            self
+        }
+
+        fn button(&mut self, btn: usize, pressed: bool) -> &mut Self {
+            self.switches.buttons.value = (self.switches.buttons.value & !(1 << btn)) | ((pressed as u32) << btn);
+            self
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut r = Runner::new(&[
+            "func = \"compare\"\na = \"Thrust\"\nop = \">\"\nvalue = -60",
+            "func = \"compare\"\na = \"Aileron\"\nop = \"|a|<\"\nvalue = 10",
+            "func = \"compare\"\na = \"Aileron\"\nop = \"<\"\nb = \"Elevator\"\nvalue = 5",
+            "func = \"compare\"\na = \"sw1\"\nop = \">\"",
+        ]);
+        // throttle at 20%: -60% on the -100% ~ 100% scale
+        assert_eq!(r.run(&[("Thrust", -60), ("Aileron", 0), ("Elevator", 0)], 10), [false, true, true, false]);
+        assert_eq!(r.run(&[("Thrust", -59), ("Aileron", -10), ("Elevator", -16)], 10), [true, false, false, false]);
+        assert_eq!(r.run(&[("Thrust", 100), ("Aileron", 9), ("Elevator", 5)], 10), [true, true, true, false]);
+        // inputs not given read 0
+        r.switch(1, 1);
+        assert_eq!(r.run(&[("Aileron", -9)], 10), [true, true, true, true]);
+        assert_eq!(r.run(&[("Thrust", -100), ("Aileron", 10)], 10), [false, false, false, true]);
+    }
+
+    #[test]
+    fn test_logic() {
+        let mut r = Runner::new(&[
+            "func = \"and\"\nconditions = [\"sw1:0\", \"sw2:2\"]",
+            "func = \"or\"\nconditions = [\"sw1:1\", \"btn3\"]",
+            "func = \"xor\"\nconditions = [\"ls1\", \"ls2\", \"sw3:1\"]",
+            // ls5 is evaluated after it, read from the last cycle
+            "func = \"and\"\nconditions = [\"ls1:0\", \"ls5\"]",
+            "func = \"or\"\nconditions = [\"sw4:1\", \"sw4:2\"]",
+        ]);
+        assert_eq!(r.run(&[], 10), [false, false, false, false, false]);
+        r.switch(2, 2);
+        assert_eq!(r.run(&[], 10), [true, false, true, false, false]);
+        r.button(3, true);
+        assert_eq!(r.run(&[], 10), [true, true, false, false, false]);
+        r.switch(3, 1);
+        assert_eq!(r.run(&[], 10), [true, true, true, false, false]);
+        r.switch(1, 1).button(3, false).switch(4, 2);
+        assert_eq!(r.run(&[], 10), [false, true, false, false, true]);
+        assert_eq!(r.run(&[], 10), [false, true, false, true, true]);
+    }
+
+    #[test]
+    fn test_edge() {
+        let mut r = Runner::new(&[
+            "func = \"edge\"\na = \"sw1:1\"",
+            "func = \"edge\"\na = \"sw1:1\"\nfalling = true",
+        ]);
+        // already on at start: no edge
+        r.switch(1, 1);
+        assert_eq!(r.run(&[], 10), [false, false]);
+        r.switch(1, 0);
+        assert_eq!(r.run(&[], 10), [false, true]);
+        assert_eq!(r.run(&[], 10), [false, false]);
+        r.switch(1, 1);
+        assert_eq!(r.run(&[], 10), [true, false]);
+        assert_eq!(r.run(&[], 10), [false, false]);
+        r.switch(1, 2);
+        assert_eq!(r.run(&[], 10), [false, true]);
+    }
+
+    #[test]
+    fn test_sticky() {
+        let mut r = Runner::new(&[
+            "func = \"sticky\"\nset = \"btn0\"",
+            "func = \"sticky\"\nset = \"sw1:1\"\nreset = \"sw2:1\"",
+        ]);
+        assert_eq!(r.run(&[], 10), [false, false]);
+        // a momentary button toggles it
+        r.button(0, true);
+        assert_eq!(r.run(&[], 10), [true, false]);
+        assert_eq!(r.run(&[], 10), [true, false]);
+        r.button(0, false);
+        assert_eq!(r.run(&[], 10), [true, false]);
+        r.button(0, true);
+        assert_eq!(r.run(&[], 10), [false, false]);
+
+        r.switch(1, 1);
+        assert_eq!(r.run(&[], 10), [false, true]);
+        r.switch(1, 0);
+        assert_eq!(r.run(&[], 10), [false, true]);
+        r.switch(2, 1);
+        assert_eq!(r.run(&[], 10), [false, false]);
+        // set while reset is still held
+        r.switch(1, 1);
+        assert_eq!(r.run(&[], 10), [false, true]);
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut r = Runner::new(&["func = \"timer\"\non = 100\noff = 300\nand = \"sw1:1\""]);
+        r.switch(1, 1);
+        assert_eq!(r.run(&[], 0), [true]);
+        assert_eq!(r.run(&[], 99), [true]);
+        assert_eq!(r.run(&[], 1), [false]);
+        assert_eq!(r.run(&[], 299), [false]);
+        assert_eq!(r.run(&[], 1), [true]);
+        r.switch(1, 0);
+        assert_eq!(r.run(&[], 1), [false]);
+    }
+
+    #[test]
+    fn test_delay_and_duration() {
+        let mut r = Runner::new(&[
+            "func = \"or\"\nconditions = [\"sw1:1\", \"sw1:2\"]\ndelay = 500",
+            "func = \"or\"\nconditions = [\"sw1:1\", \"sw1:2\"]\nduration = 200",
+            "func = \"or\"\nconditions = [\"sw1:1\", \"sw1:2\"]\ndelay = 100\nduration = 100",
+        ]);
+        assert_eq!(r.run(&[], 10), [false, false, false]);
+        r.switch(1, 1);
+        assert_eq!(r.run(&[], 10), [false, true, false]);
+        assert_eq!(r.run(&[], 100), [false, true, true]);
+        assert_eq!(r.run(&[], 99), [false, true, true]);
+        assert_eq!(r.run(&[], 1), [false, false, false]);
+        assert_eq!(r.run(&[], 290), [false, false, false]);
+        assert_eq!(r.run(&[], 10), [true, false, false]);
+        // switching between on positions keeps the function on
+        r.switch(1, 2);
+        assert_eq!(r.run(&[], 10), [true, false, false]);
+        // released before the delay: never turns on
+        r.switch(1, 0);
+        assert_eq!(r.run(&[], 10), [false, false, false]);
+        r.switch(1, 1);
+        assert_eq!(r.run(&[], 400), [false, true, false]);
+        r.switch(1, 0);
+        assert_eq!(r.run(&[], 400), [false, false, false]);
+    }
+
+    #[test]
+    fn test_validate_logical_switch() {
+        assert!(parse("func = \"and\"\nconditions = [\"sw1:1\"]").validate(1).is_err());
+        assert!(parse("func = \"and\"\nconditions = [\"sw1:1\", \"ls2\"]").validate(1).is_err());
+        assert!(parse("func = \"and\"\nconditions = [\"sw1:1\", \"ls2\"]").validate(2).is_ok());
+        assert!(parse("func = \"timer\"\non = 0\noff = 0").validate(1).is_err());
+        assert!(parse("func = \"timer\"\non = 100\noff = 0\ndelay = 600001").validate(1).is_err());
+        assert!(parse("func = \"compare\"\na = \"Thrust\"\nop = \">\"\nvalue = 201").validate(1).is_err());
+        assert!(parse("func = \"compare\"\na = \"ls3\"\nop = \">\"").validate(2).is_err());
+        assert!(toml::from_str::<LogicalSwitch>("func = \"compare\"\na = \"Thrust\"\nop = \">=\"").is_err());
+        assert!(toml::from_str::<LogicalSwitch>("func = \"nand\"\nconditions = []").is_err());
+        assert!(toml::from_str::<LogicalSwitch>("func = \"sticky\"\nset = \"sw1\"").is_err());
+        assert!(toml::from_str::<LogicalSwitch>("func = \"sticky\"\nset = \"ls33\"").is_err());
+    }
+}
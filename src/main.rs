@@ -3,13 +3,28 @@ use rpos::{
     server_client::{server_init, Client},
 };
 mod adc;
+mod adc_spi;
 mod calibrate;
+mod curve;
+mod device;
 mod mixer;
+mod model;
 mod elrs_tx;
+mod ev_dev;
+mod filter;
+mod flight_mode;
+mod gpio_switch;
 mod joy_dev;
 mod joysticks_test;
+mod logical_switch;
 mod gampad;
 mod msgbus;
+mod output;
+mod record;
+mod replay;
+mod sim_input;
+mod stick_mode;
+mod trim;
 
 
 pub const CALIBRATE_FILENAME: &str = "joystick.toml";
@@ -42,6 +57,16 @@ pub fn client_process_args<T:clap::Parser>(
     ret.ok()
 }
 
+// written to <filename>.tmp then renamed, a crash never leaves a half written file
+pub fn write_file_atomic(filename: &str, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    let tmp_filename = format!("{}.tmp", filename);
+    let mut file = std::fs::File::create(&tmp_filename)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_filename, filename)
+}
+
 fn main() {
     const SOCKET_PATH: &str = "./rpsocket";
     let cli = Cli::parse();
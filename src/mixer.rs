@@ -1,31 +1,131 @@
-use std::{fs, io::Read};
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Mutex,
+};
+use std::time::Instant;
 
+use clap::{Parser, Subcommand, ValueEnum};
 use rpos::thread_logln;
 
 use crate::adc::AdcRawMsg;
-use crate::calibrate::{
-    CalibrationData,
-    JoystickChannel::{self, *},
+use crate::calibrate::{CalibrationData, ChannelInfo};
+use crate::flight_mode::FlightModeFader;
+use crate::logical_switch::LogicalSwitches;
+use crate::model::{MixInputs, Model, Switches, MIXER_CHANNEL_NUM};
+use crate::msgbus::{
+    adc_raw_subscriber, button_subscriber, flight_mode_publisher, mixer_out_publisher, switch_state_subscriber,
 };
-use crate::msgbus::{adc_raw_subscriber, mixer_out_publisher};
-use crate::CALIBRATE_FILENAME;
+use crate::stick_mode::StickMode;
+use crate::trim::{trim_filename, TrimButtons, TrimConfig, Trims};
+use crate::{client_process_args, CALIBRATE_FILENAME};
+
+// calibrated inputs the mixer reads, by name
+const THRUST: &str = "Thrust";
+const DIRECTION: &str = "Direction";
+const AILERON: &str = "Aileron";
+const ELEVATOR: &str = "Elevator";
+const STICKS: [&str; 4] = [THRUST, DIRECTION, AILERON, ELEVATOR];
+
+// StickMode::number() of the running mixer, 0 when it's not running
+static STICK_MODE: AtomicU8 = AtomicU8::new(0);
+
+// trims of the running mixer, shared with `mixer trim`
+static TRIMS: Mutex<Option<LiveTrims>> = Mutex::new(None);
+
+#[derive(Parser)]
+#[command(name="mixer", about = "mix calibrated inputs into mixer_out, run without command to start", long_about = None)]
+struct Cli {
+    /// model file with the mix lines, the default AETR model is used if it doesn't exist.
+    #[arg(short, long, default_value = "model.toml")]
+    model: String,
+
+    #[command(subcommand)]
+    command: Option<MixerCommand>,
+}
+
+#[derive(Subcommand)]
+enum MixerCommand {
+    /// select stick mode 1~4, saved to the calibration file and applied without recalibrating.
+    Mode {
+        mode: StickMode,
+    },
+    /// move or reset the trim of an input, saved to <model>.trim.toml.
+    Trim {
+        input: String,
+        action: TrimAction,
+    },
+}
 
+#[derive(Clone, Copy, ValueEnum)]
+enum TrimAction {
+    Up,
+    Down,
+    Reset,
+}
+
+struct LiveTrims {
+    configs: Vec<TrimConfig>,
+    trims: Trims,
+    filename: String,
+}
+
+impl LiveTrims {
+    fn load(model: &Model, model_filename: &str) -> Result<Self, String> {
+        let filename = trim_filename(model_filename);
+        Ok(LiveTrims {
+            configs: model.trims.clone(),
+            trims: Trims::load(&filename)?,
+            filename,
+        })
+    }
+
+    // returns the new trim value, saving is left to the caller
+    fn apply(&mut self, input: &str, action: TrimAction) -> Result<i32, String> {
+        let config = self
+            .configs
+            .iter()
+            .find(|x| x.input.eq_ignore_ascii_case(input))
+            .ok_or_else(|| format!("no trim configured for {}", input))?;
+        let value = match action {
+            TrimAction::Up => self.trims.step(config, 1),
+            TrimAction::Down => self.trims.step(config, -1),
+            TrimAction::Reset => {
+                self.trims.reset(input);
+                0
+            }
+        };
+        Ok(value)
+    }
+}
+
+// channel value:0 ~ 10000
 #[derive(Clone)]
 pub struct MixerOutMsg{
-    pub thrust: u16,
-    pub direction: u16,
-    pub aileron: u16,
-    pub elevator: u16,
+    pub channels: [u16; MIXER_CHANNEL_NUM],
 }
 
-fn cal_mixout(channel: JoystickChannel, raw: &AdcRawMsg, cal_data: &CalibrationData) -> u16 {
-    let channel_cal_info = &cal_data.channel_infos[channel as usize];
-
+fn cal_mixout(channel_cal_info: &ChannelInfo, raw: &AdcRawMsg) -> u16 {
     let raw_val = raw.value[channel_cal_info.index as usize]
         .clamp(channel_cal_info.min, channel_cal_info.max) as i32;
+    let min = channel_cal_info.min as i32;
+    let max = channel_cal_info.max as i32;
 
-    let mut ret = (raw_val - channel_cal_info.min as i32) as u32 * 10000
-        / (channel_cal_info.max as i32 - channel_cal_info.min as i32) as u32;
+    // min->center maps to 0~5000 and center->max to 5000~10000, the deadband around center stays at 5000
+    let mut ret = match channel_cal_info.center {
+        Some(center) => {
+            let center = (center as i32).clamp(min, max);
+            let low = (center - channel_cal_info.deadband as i32).max(min);
+            let high = (center + channel_cal_info.deadband as i32).min(max);
+            if raw_val < low {
+                (raw_val - min) as u32 * 5000 / (low - min) as u32
+            } else if raw_val > high {
+                5000 + (raw_val - high) as u32 * 5000 / (max - high) as u32
+            } else {
+                5000
+            }
+        }
+        None => (raw_val - min) as u32 * 10000 / (max - min) as u32,
+    };
 
     if channel_cal_info.rev {
         ret = 10000 - ret;
@@ -34,27 +134,178 @@ fn cal_mixout(channel: JoystickChannel, raw: &AdcRawMsg, cal_data: &CalibrationD
     ret as u16
 }
 
-fn mixer_main(_argc: u32, _argv: *const &str) {
-    let mut rx = adc_raw_subscriber();
-    let tx = mixer_out_publisher();
-    let mut toml_str = String::new();
-    if let Ok(mut file) = fs::File::open(CALIBRATE_FILENAME) {
-        file.read_to_string(&mut toml_str).unwrap();
+// calibrated inputs by the name the model reads them with, the sticks follow the stick mode
+fn mix_inputs(cal_data: &CalibrationData, mode: StickMode) -> Vec<(&str, &ChannelInfo)> {
+    let mut ret: Vec<(&str, &ChannelInfo)> = STICKS
+        .iter()
+        .filter_map(|function| cal_data.get(mode.remap(function, cal_data.calibrated_mode)).map(|info| (*function, info)))
+        .collect();
+    let others = cal_data.channel_infos.iter().filter(|x| !STICKS.iter().any(|s| s.eq_ignore_ascii_case(&x.name)));
+    ret.extend(others.map(|x| (x.name.as_str(), x)));
+    ret
+}
+
+fn set_stick_mode(mode: StickMode) -> Result<(), String> {
+    let mut cal_data = CalibrationData::load(CALIBRATE_FILENAME, &[]).map_err(|e| e.to_string())?;
+    cal_data.stick_mode = mode;
+    cal_data.save(CALIBRATE_FILENAME).map_err(|e| e.to_string())?;
+    if STICK_MODE.load(Ordering::SeqCst) != 0 {
+        STICK_MODE.store(mode.number(), Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+fn load_model(filename: &str) -> Result<Model, String> {
+    if std::path::Path::new(filename).exists() {
+        Model::load(filename)
     } else {
-        thread_logln!("no joystick.toml found. please calibrate joysticks first!");
+        thread_logln!("no {} found, using the default AETR model.", filename);
+        Ok(Model::default())
+    }
+}
+
+// works on the files when the mixer isn't running
+fn trim_command(model_filename: &str, input: &str, action: TrimAction) -> Result<i32, String> {
+    let mut guard = TRIMS.lock().unwrap();
+    let Some(live) = guard.as_mut() else {
+        let mut live = LiveTrims::load(&load_model(model_filename)?, model_filename)?;
+        let value = live.apply(input, action)?;
+        live.trims.save(&live.filename)?;
+        return Ok(value);
+    };
+    let value = live.apply(input, action)?;
+    // the mixer loop waits on the lock, the file is written after it is released
+    let (trims, filename) = (live.trims.clone(), live.filename.clone());
+    drop(guard);
+    trims.save(&filename)?;
+    Ok(value)
+}
+
+fn mixer_main(argc: u32, argv: *const &str) {
+    let ret = client_process_args::<Cli>(argc, argv);
+    if ret.is_none() {
         return;
     }
 
-    let cal_data = toml::from_str::<CalibrationData>(toml_str.as_str()).unwrap();
+    let args = ret.unwrap();
+    match args.command {
+        Some(MixerCommand::Mode { mode }) => {
+            match set_stick_mode(mode) {
+                Ok(()) => thread_logln!("stick mode {}.", mode.number()),
+                Err(e) => thread_logln!("failed to set stick mode, {}.", e),
+            }
+            return;
+        }
+        Some(MixerCommand::Trim { input, action }) => {
+            match trim_command(&args.model, &input, action) {
+                Ok(value) => thread_logln!("{} trim:{}.", input, value),
+                Err(e) => thread_logln!("failed to trim, {}.", e),
+            }
+            return;
+        }
+        None => {}
+    }
+
+    let model = match load_model(&args.model) {
+        Ok(model) => model,
+        Err(e) => {
+            thread_logln!("failed to load model, {}.", e);
+            return;
+        }
+    };
+    let live = match LiveTrims::load(&model, &args.model) {
+        Ok(live) => live,
+        Err(e) => {
+            thread_logln!("failed to load trims, {}.", e);
+            return;
+        }
+    };
+    // stick mode may swap any of the sticks
+    let mut required = model.input_names();
+    if required.iter().any(|x| STICKS.iter().any(|s| s.eq_ignore_ascii_case(x))) {
+        required.extend(STICKS);
+    }
+
+    let mut rx = adc_raw_subscriber();
+    let mut switch_rx = switch_state_subscriber();
+    let mut button_rx = button_subscriber();
+    let tx = mixer_out_publisher();
+    let flight_mode_tx = flight_mode_publisher();
+    let cal_data = match CalibrationData::load(CALIBRATE_FILENAME, &required) {
+        Ok(data) => data,
+        Err(e) => {
+            thread_logln!("failed to load calibration, {}. please calibrate joysticks first!", e);
+            return;
+        }
+    };
+    let mut mode = cal_data.stick_mode;
+    STICK_MODE.store(mode.number(), Ordering::SeqCst);
+    thread_logln!("stick mode {}.", mode.number());
+    let mut inputs = mix_inputs(&cal_data, mode);
+    let mut switches = Switches::default();
+    let mut trim_buttons = TrimButtons::new(&live.configs);
+    let mut logical_switches = LogicalSwitches::new(&model.logical_switches);
+    let mut fader = FlightModeFader::new(&model);
+    let mut flight_mode = None;
+    *TRIMS.lock().unwrap() = Some(live);
     loop {
         let x = rx.read();
+        let now = Instant::now();
+        if let Some(msg) = switch_rx.try_read() {
+            switches.physical = msg;
+        }
+        if let Some(msg) = button_rx.try_read() {
+            switches.buttons = msg;
+        }
+        let selected = StickMode::from_number(STICK_MODE.load(Ordering::SeqCst)).unwrap();
+        if selected != mode {
+            mode = selected;
+            inputs = mix_inputs(&cal_data, mode);
+        }
+        // centered, -5000 ~ 5000
+        let values: Vec<(&str, i32)> = inputs.iter().map(|(name, info)| (*name, cal_mixout(info, &x) as i32 - 5000)).collect();
+
+        let mut guard = TRIMS.lock().unwrap();
+        let live = guard.as_mut().unwrap();
+        // logical switches compare the inputs before rates and trims
+        let raw_inputs = MixInputs {
+            inputs: &values,
+            switches: &switches,
+            trims: &live.trims,
+            flight_mode: 0,
+        };
+        switches.logical = logical_switches.update(&model.logical_switches, &raw_inputs, now);
+        // saved once the lock is released
+        let changed_trims = trim_buttons
+            .update(&live.configs, &mut live.trims, &switches)
+            .then(|| (live.trims.clone(), live.filename.clone()));
+        let msg = fader.update(&model, &switches, now);
+        if flight_mode != Some(msg) {
+            if flight_mode.is_none_or(|x| x.index != msg.index) {
+                let name = (msg.index as usize).checked_sub(1).map_or("none", |i| &model.flight_modes[i].name);
+                thread_logln!("flight mode {}.", name);
+            }
+            flight_mode = Some(msg);
+            flight_mode_tx.publish(msg);
+        }
         let mixer_out = MixerOutMsg {
-            thrust: cal_mixout(Thrust, &x, &cal_data),
-            direction: cal_mixout(Direction, &x, &cal_data),
-            aileron: cal_mixout(Aileron, &x, &cal_data),
-            elevator: cal_mixout(Elevator, &x, &cal_data),
+            channels: fader.mix(
+                &model,
+                &MixInputs {
+                    inputs: &values,
+                    switches: &switches,
+                    trims: &live.trims,
+                    flight_mode: msg.index as usize,
+                },
+            ),
         };
+        drop(guard);
         tx.publish(mixer_out);
+        if let Some((trims, filename)) = changed_trims {
+            if let Err(e) = trims.save(&filename) {
+                thread_logln!("failed to save trims, {}.", e);
+            }
+        }
     }
 }
 
@@ -65,7 +316,8 @@ fn register() {
 
 #[cfg(test)]
 mod tests {
-    use crate::calibrate::ChannelInfo;
+    use crate::adc::AdcSource;
+    use crate::calibrate::InputKind;
 
     use super::*;
     use rand::prelude::*;
@@ -74,14 +326,15 @@ mod tests {
     fn test_cal_mixout() {
         let mut rng = thread_rng();
         let mut get_random_channel_value = || rng.gen_range(300..1400) as i16;
-        let mut adc_raw = AdcRawMsg {
-            value: [
+        let mut adc_raw = AdcRawMsg::new(
+            AdcSource::Unknown,
+            &[
                 500,
                 100,
                 1600,
                 get_random_channel_value(),
             ],
-        };
+        );
         let mut cal_data = CalibrationData {
             channel_infos: [
                 ChannelInfo {
@@ -89,44 +342,117 @@ mod tests {
                     index: 0,
                     min: 200,
                     max: 1500,
+                    center: None,
+                    deadband: 0,
                     rev: false,
+                    kind: InputKind::NonCentering,
                 },
                 ChannelInfo {
                     name: "direction".to_string(),
                     index: 1,
                     min: 200,
                     max: 1500,
+                    center: None,
+                    deadband: 0,
                     rev: false,
+                    kind: InputKind::NonCentering,
                 },
                 ChannelInfo {
-                    name: "aliron".to_string(),
+                    name: "aileron".to_string(),
                     index: 2,
                     min: 200,
                     max: 1500,
+                    center: None,
+                    deadband: 0,
                     rev: false,
+                    kind: InputKind::NonCentering,
                 },
                 ChannelInfo {
-                    name: "ele".to_string(),
+                    name: "elevator".to_string(),
                     index: 3,
                     min: 200,
                     max: 1500,
+                    center: None,
+                    deadband: 0,
                     rev: false,
+                    kind: InputKind::NonCentering,
                 },
             ]
             .to_vec(),
             channel_indexs: [0; 4].to_vec(),
+            ..Default::default()
         };
 
-        assert_eq!(cal_mixout(JoystickChannel::Thrust, &adc_raw, &cal_data), ((500 - 200) as u32 *10000  / (1500 - 200) )as u16);
-        assert_eq!(cal_mixout(JoystickChannel::Direction, &adc_raw, &cal_data), ((200 - 200) as u32 *10000  / (1500 - 200) )as u16);
-        assert_eq!(cal_mixout(JoystickChannel::Aileron, &adc_raw, &cal_data), ((1500 - 200) as u32 *10000  / (1500 - 200) )as u16);
+        assert_eq!(cal_mixout(cal_data.get(THRUST).unwrap(), &adc_raw), ((500 - 200) as u32 *10000  / (1500 - 200) )as u16);
+        assert_eq!(cal_mixout(cal_data.get(DIRECTION).unwrap(), &adc_raw), ((200 - 200) as u32 *10000  / (1500 - 200) )as u16);
+        assert_eq!(cal_mixout(cal_data.get(AILERON).unwrap(), &adc_raw), ((1500 - 200) as u32 *10000  / (1500 - 200) )as u16);
 
         for _ in 0..1000{
-            assert!(cal_mixout(JoystickChannel::Elevator, &adc_raw, &cal_data) <= 10000 );
+            assert!(cal_mixout(cal_data.get(ELEVATOR).unwrap(), &adc_raw) <= 10000 );
             adc_raw.value[3] = get_random_channel_value();
         }
 
         cal_data.channel_infos[0].rev = true;
-        assert_eq!(cal_mixout(JoystickChannel::Thrust, &adc_raw, &cal_data), 10000 - ((500 - 200) as u32 *10000  / (1500 - 200) )as u16);
+        assert_eq!(cal_mixout(cal_data.get(THRUST).unwrap(), &adc_raw), 10000 - ((500 - 200) as u32 *10000  / (1500 - 200) )as u16);
+
+        cal_data.channel_infos[3].name = "ELEVATOR".to_string();
+        cal_data.channel_infos.push(ChannelInfo { name: "Pot1".to_string(), ..cal_data.channel_infos[0].clone() });
+        let names = |cal_data: &CalibrationData, mode| {
+            mix_inputs(cal_data, mode).iter().map(|(function, x)| format!("{}:{}", function, x.name)).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&cal_data, StickMode::Mode2),
+            ["Thrust:thrust", "Direction:direction", "Aileron:aileron", "Elevator:ELEVATOR", "Pot1:Pot1"]
+        );
+        assert_eq!(
+            names(&cal_data, StickMode::Mode1),
+            ["Thrust:ELEVATOR", "Direction:direction", "Aileron:aileron", "Elevator:thrust", "Pot1:Pot1"]
+        );
+        cal_data.calibrated_mode = StickMode::Mode1;
+        assert_eq!(names(&cal_data, StickMode::Mode1)[..4], ["Thrust:thrust", "Direction:direction", "Aileron:aileron", "Elevator:ELEVATOR"]);
+        assert_eq!(names(&cal_data, StickMode::Mode3)[..4], ["Thrust:thrust", "Direction:aileron", "Aileron:direction", "Elevator:ELEVATOR"]);
+    }
+
+    #[test]
+    fn test_cal_mixout_center() {
+        let mut cal_data = CalibrationData {
+            channel_infos: [ChannelInfo {
+                name: "thrust".to_string(),
+                index: 0,
+                min: 200,
+                max: 1500,
+                center: Some(1000),
+                deadband: 0,
+                rev: false,
+                kind: InputKind::Centering,
+            }]
+            .to_vec(),
+            channel_indexs: [0].to_vec(),
+            ..Default::default()
+        };
+        let mixout = |raw: i16, cal_data: &CalibrationData| {
+            cal_mixout(&cal_data.channel_infos[0], &AdcRawMsg::new(AdcSource::Unknown, &[raw]))
+        };
+
+        assert_eq!(mixout(200, &cal_data), 0);
+        assert_eq!(mixout(600, &cal_data), 2500);
+        assert_eq!(mixout(1000, &cal_data), 5000);
+        assert_eq!(mixout(1250, &cal_data), 7500);
+        assert_eq!(mixout(1500, &cal_data), 10000);
+        assert_eq!(mixout(100, &cal_data), 0);
+        assert_eq!(mixout(1600, &cal_data), 10000);
+
+        cal_data.channel_infos[0].rev = true;
+        assert_eq!(mixout(600, &cal_data), 7500);
+        assert_eq!(mixout(1000, &cal_data), 5000);
+
+        cal_data.channel_infos[0].rev = false;
+        cal_data.channel_infos[0].deadband = 100;
+        assert_eq!(mixout(950, &cal_data), 5000);
+        assert_eq!(mixout(1100, &cal_data), 5000);
+        assert_eq!(mixout(200, &cal_data), 0);
+        assert_eq!(mixout(550, &cal_data), 2500);
+        assert_eq!(mixout(1300, &cal_data), 7500);
+        assert_eq!(mixout(1500, &cal_data), 10000);
     }
 }
@@ -0,0 +1,1032 @@
+use crate::{
+    curve::Curve,
+    flight_mode::FlightMode,
+    ev_dev::{ButtonMsg, BUTTON_NUM},
+    gpio_switch::{SwitchStateMsg, SWITCH_NUM},
+    logical_switch::{validate_logical_refs, LogicalSwitch, LOGICAL_SWITCH_NUM},
+    output::OutputConfig,
+    trim::{TrimConfig, Trims},
+};
+
+pub const MIXER_CHANNEL_NUM: usize = 16;
+
+// mix values are centered: -5000(-100%) ~ 5000(+100%), outputs are 0 ~ 10000
+pub const FULL_SCALE: i32 = 5000;
+
+// model file, e.g.
+// [[mix]]
+// channel = 1
+// source = "Aileron"
+// weight = 50
+// [[mix]]
+// channel = 1
+// source = "Elevator"
+// weight = 50
+// [[rates]]
+// input = "Aileron"
+// switch = "sw1"
+// sets = [{ rate = 100, expo = 30 }, { rate = 60, expo = 20 }]
+// see curve.rs for [[curves]], trim.rs for [[trims]], output.rs for [[outputs]]
+// flight_mode.rs for [[flight_modes]] and logical_switch.rs for [[logical_switches]]
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Model {
+    #[serde(default)]
+    pub mix: Vec<MixLine>,
+    #[serde(default)]
+    pub rates: Vec<Rates>,
+    #[serde(default)]
+    pub curves: Vec<Curve>,
+    #[serde(default)]
+    pub trims: Vec<TrimConfig>,
+    #[serde(default)]
+    pub outputs: Vec<OutputConfig>,
+    #[serde(default)]
+    pub flight_modes: Vec<FlightMode>,
+    #[serde(default)]
+    pub logical_switches: Vec<LogicalSwitch>,
+}
+
+// dual rates and expo of an input, applied before the mix lines read it
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct Rates {
+    pub input: String,
+    // selects sets[position], the last set is used for higher positions. sets[0] is used without a switch
+    #[serde(default)]
+    pub switch: Option<SwitchRef>,
+    pub sets: Vec<RateSet>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct RateSet {
+    // percent of full travel
+    #[serde(default = "default_weight")]
+    pub rate: i32,
+    // percent, -100 ~ 100, positive softens the center
+    #[serde(default)]
+    pub expo: i32,
+    // name of a curve applied after expo
+    #[serde(default)]
+    pub curve: Option<String>,
+}
+
+fn validate_rates(rates: &[Rates]) -> Result<(), String> {
+    for (i, x) in rates.iter().enumerate() {
+        if rates[..i].iter().any(|y| y.input.eq_ignore_ascii_case(&x.input)) {
+            return Err(format!("rates of {} are given twice", x.input));
+        }
+        if x.sets.is_empty() {
+            return Err(format!("rates of {} have no sets", x.input));
+        }
+        if let Some(set) = x.sets.iter().find(|x| !(-100..=100).contains(&x.expo) || !(0..=200).contains(&x.rate)) {
+            return Err(format!(
+                "rates of {}: expo {} should be -100 ~ 100, rate {} should be 0 ~ 200",
+                x.input, set.expo, set.rate
+            ));
+        }
+    }
+    Ok(())
+}
+
+impl Rates {
+    fn apply(&self, value: i32, switches: &Switches, model: &Model) -> i32 {
+        let position = self.switch.map_or(0, |x| switches.position(x.0) as usize);
+        let set = &self.sets[position.min(self.sets.len() - 1)];
+        model.apply_curve(set.curve.as_deref(), expo(value, set.expo)) * set.rate / 100
+    }
+}
+
+// value * (1 - expo) + value^3 * expo, value normalized to -1 ~ 1
+fn expo(value: i32, expo: i32) -> i32 {
+    let value = value as i64;
+    let full_scale = FULL_SCALE as i64;
+    let cubic = value * value * value / (full_scale * full_scale);
+    ((value * (100 - expo as i64) + cubic * expo as i64) / 100) as i32
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct MixLine {
+    // for flight modes to enable or disable the line
+    #[serde(default)]
+    pub name: Option<String>,
+    // skipped unless a flight mode enables it
+    #[serde(default)]
+    pub disabled: bool,
+    // output channel, 1 ~ 16
+    pub channel: u8,
+    pub source: Source,
+    // name of a curve applied to the source before weight
+    #[serde(default)]
+    pub curve: Option<String>,
+    // percent, -500 ~ 500
+    #[serde(default = "default_weight")]
+    pub weight: i32,
+    // percent, -500 ~ 500, added after weight
+    #[serde(default)]
+    pub offset: i32,
+    #[serde(default)]
+    pub mode: MixMode,
+    // the line is skipped unless the switch is in the given position
+    #[serde(default)]
+    pub switch: Option<SwitchCondition>,
+}
+
+fn default_weight() -> i32 {
+    100
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MixMode {
+    // added to the lines above
+    #[default]
+    Add,
+    // discards the lines above
+    Replace,
+    // scales the lines above, 100% keeps them unchanged
+    Multiply,
+}
+
+// "max" for a +100% constant, a switch (see SwitchId), anything else is a calibrated input name
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum Source {
+    Max,
+    Switch(SwitchId),
+    Input(String),
+}
+
+impl TryFrom<String> for Source {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.eq_ignore_ascii_case("max") {
+            return Ok(Source::Max);
+        }
+        if let Some(id) = parse_switch_id(&s)? {
+            return Ok(Source::Switch(id));
+        }
+        Ok(Source::Input(s))
+    }
+}
+
+// "sw1" ~ "sw8" physical switches, "btn0" ~ "btn31" buttons as in ButtonMsg,
+// "ls1" ~ "ls32" logical switches. buttons and logical switches have 2 positions: 0 off, 1 on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SwitchId {
+    Physical(usize),
+    Button(usize),
+    Logical(usize),
+}
+
+// name prefix, first and last number
+type SwitchKind = (&'static str, usize, usize, fn(usize) -> SwitchId);
+
+// Some(id) for a switch name, None if it's not one
+fn parse_switch_id(s: &str) -> Result<Option<SwitchId>, String> {
+    let kinds: [SwitchKind; 3] = [
+        ("sw", 1, SWITCH_NUM, SwitchId::Physical),
+        ("btn", 0, BUTTON_NUM - 1, SwitchId::Button),
+        ("ls", 1, LOGICAL_SWITCH_NUM, SwitchId::Logical),
+    ];
+    for (prefix, first, last, id) in kinds {
+        let number = match s.get(..prefix.len()) {
+            Some(x) if x.eq_ignore_ascii_case(prefix) => &s[prefix.len()..],
+            _ => continue,
+        };
+        return match number.parse::<usize>() {
+            Ok(n) if (first..=last).contains(&n) => Ok(Some(id(n - first))),
+            Ok(_) => Err(format!("{}: only {}{} ~ {}{} are available", s, prefix, first, prefix, last)),
+            Err(_) => Ok(None),
+        };
+    }
+    Ok(None)
+}
+
+// a switch by name, see SwitchId
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct SwitchRef(pub SwitchId);
+
+impl TryFrom<String> for SwitchRef {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        parse_switch_id(&s)?
+            .map(SwitchRef)
+            .ok_or_else(|| format!("{} should be sw<n>, btn<n> or ls<n>", s))
+    }
+}
+
+// "<switch>:<position>", e.g. "sw1:2" is switch 1 at position 2
+// buttons and logical switches may leave out the position for on: "ls3" is "ls3:1"
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct SwitchCondition {
+    pub switch: SwitchId,
+    pub position: u8,
+}
+
+impl TryFrom<String> for SwitchCondition {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let err = || format!("{} should be sw<n>:<position>, btn<n>[:<position>] or ls<n>[:<position>]", s);
+        let (switch, position) = match s.split_once(':') {
+            Some((switch, position)) => (switch, Some(position.parse().map_err(|_| err())?)),
+            None => (s.as_str(), None),
+        };
+        let switch = parse_switch_id(switch)?.ok_or_else(err)?;
+        let position = match (switch, position) {
+            (_, Some(position)) => position,
+            (SwitchId::Physical(_), None) => return Err(err()),
+            (_, None) => 1,
+        };
+        Ok(SwitchCondition { switch, position })
+    }
+}
+
+impl SwitchCondition {
+    pub fn is_active(&self, switches: &Switches) -> bool {
+        switches.position(self.switch) == self.position
+    }
+}
+
+// every switch the model can read in one cycle
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Switches {
+    pub physical: SwitchStateMsg,
+    pub buttons: ButtonMsg,
+    // see logical_switch.rs
+    pub logical: [bool; LOGICAL_SWITCH_NUM],
+}
+
+impl From<SwitchStateMsg> for Switches {
+    fn from(physical: SwitchStateMsg) -> Self {
+        Switches {
+            physical,
+            ..Default::default()
+        }
+    }
+}
+
+impl Switches {
+    pub fn position(&self, id: SwitchId) -> u8 {
+        match id {
+            SwitchId::Physical(i) => self.physical.value[i],
+            SwitchId::Button(i) => (self.buttons.value >> i & 1) as u8,
+            SwitchId::Logical(i) => self.logical[i] as u8,
+        }
+    }
+
+    pub fn positions(&self, id: SwitchId) -> u8 {
+        match id {
+            SwitchId::Physical(i) => self.physical.positions[i].max(2),
+            _ => 2,
+        }
+    }
+}
+
+// what the mix lines read in one cycle
+#[derive(Clone, Copy)]
+pub struct MixInputs<'a> {
+    // calibrated inputs by name, -5000 ~ 5000
+    pub inputs: &'a [(&'a str, i32)],
+    pub switches: &'a Switches,
+    pub trims: &'a Trims,
+    // see select_flight_mode
+    pub flight_mode: usize,
+}
+
+impl MixInputs<'_> {
+    pub fn value(&self, source: &Source) -> i32 {
+        match source {
+            Source::Max => FULL_SCALE,
+            Source::Switch(id) => {
+                // 2-position: -100%/+100%, 3-position: -100%/0/+100%
+                let positions = self.switches.positions(*id) as i32;
+                let position = (self.switches.position(*id) as i32).min(positions - 1);
+                position * 2 * FULL_SCALE / (positions - 1) - FULL_SCALE
+            }
+            Source::Input(name) => self
+                .inputs
+                .iter()
+                .find(|(x, _)| x.eq_ignore_ascii_case(name))
+                .map_or(0, |(_, v)| *v),
+        }
+    }
+}
+
+impl Default for Model {
+    // one input per channel in AETR order, what elrs_tx used to send
+    fn default() -> Self {
+        let line = |channel, source: &str| MixLine {
+            name: None,
+            disabled: false,
+            channel,
+            source: Source::Input(source.to_string()),
+            curve: None,
+            weight: 100,
+            offset: 0,
+            mode: MixMode::Add,
+            switch: None,
+        };
+        Model {
+            mix: vec![line(1, "Aileron"), line(2, "Elevator"), line(3, "Thrust"), line(4, "Direction")],
+            rates: Vec::new(),
+            curves: Vec::new(),
+            trims: Vec::new(),
+            outputs: Vec::new(),
+            flight_modes: Vec::new(),
+            logical_switches: Vec::new(),
+        }
+    }
+}
+
+impl Model {
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let toml_str = std::fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let model = toml::from_str::<Model>(&toml_str).map_err(|e| format!("{}: {}", filename, e))?;
+        model.validate()?;
+        Ok(model)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for line in self.mix.iter() {
+            if !(1..=MIXER_CHANNEL_NUM).contains(&(line.channel as usize)) {
+                return Err(format!("channel {} should be 1 ~ {}", line.channel, MIXER_CHANNEL_NUM));
+            }
+            if !(-500..=500).contains(&line.weight) || !(-500..=500).contains(&line.offset) {
+                return Err(format!(
+                    "channel {}: weight {} and offset {} should be -500 ~ 500",
+                    line.channel, line.weight, line.offset
+                ));
+            }
+        }
+        validate_rates(&self.rates)?;
+        for (i, curve) in self.curves.iter().enumerate() {
+            if self.curves[..i].iter().any(|x| x.name == curve.name) {
+                return Err(format!("curve {} is given twice", curve.name));
+            }
+            curve.validate()?;
+        }
+        let used_curves = self
+            .mix
+            .iter()
+            .filter_map(|x| x.curve.as_ref())
+            .chain(self.all_rates().flat_map(|x| x.sets.iter().filter_map(|x| x.curve.as_ref())));
+        for name in used_curves {
+            if !self.curves.iter().any(|x| x.name == *name) {
+                return Err(format!("curve {} is not defined", name));
+            }
+        }
+        for (i, trim) in self.trims.iter().enumerate() {
+            if self.trims[..i].iter().any(|x| x.input.eq_ignore_ascii_case(&trim.input)) {
+                return Err(format!("trim of {} is given twice", trim.input));
+            }
+            if trim.step <= 0 || !(0..=FULL_SCALE).contains(&trim.range) {
+                return Err(format!(
+                    "trim of {}: step {} should be positive, range {} should be 0 ~ {}",
+                    trim.input, trim.step, trim.range, FULL_SCALE
+                ));
+            }
+        }
+        for (i, output) in self.outputs.iter().enumerate() {
+            if self.outputs[..i].iter().any(|x| x.channel == output.channel) {
+                return Err(format!("output channel {} is given twice", output.channel));
+            }
+            output.validate()?;
+        }
+        for (i, mode) in self.flight_modes.iter().enumerate() {
+            if self.flight_modes[..i].iter().any(|x| x.name.eq_ignore_ascii_case(&mode.name)) {
+                return Err(format!("flight mode {} is given twice", mode.name));
+            }
+            mode.validate()?;
+            validate_rates(&mode.rates).map_err(|e| format!("flight mode {}: {}", mode.name, e))?;
+            for name in mode.enable_mix.iter().chain(mode.disable_mix.iter()) {
+                if !self.mix.iter().any(|x| x.name.as_ref() == Some(name)) {
+                    return Err(format!("flight mode {}: mix line {} is not defined", mode.name, name));
+                }
+            }
+        }
+        let count = self.logical_switches.len();
+        if count > LOGICAL_SWITCH_NUM {
+            return Err(format!("{} logical switches, only {} are available", count, LOGICAL_SWITCH_NUM));
+        }
+        for (i, ls) in self.logical_switches.iter().enumerate() {
+            ls.validate(count).map_err(|e| format!("ls{}: {}", i + 1, e))?;
+        }
+        validate_logical_refs(&self.switch_ids(), count)
+    }
+
+    // switches read outside the logical switches
+    fn switch_ids(&self) -> Vec<SwitchId> {
+        let sources = self.mix.iter().filter_map(|x| match x.source {
+            Source::Switch(id) => Some(id),
+            _ => None,
+        });
+        let conditions = self
+            .mix
+            .iter()
+            .filter_map(|x| x.switch)
+            .chain(self.flight_modes.iter().map(|x| x.switch))
+            .chain(self.trims.iter().flat_map(|x| [x.up, x.down]).flatten())
+            .map(|x| x.switch);
+        let rates = self.all_rates().filter_map(|x| x.switch).map(|x| x.0);
+        sources.chain(conditions).chain(rates).collect()
+    }
+
+    fn all_rates(&self) -> impl Iterator<Item = &Rates> {
+        self.rates.iter().chain(self.flight_modes.iter().flat_map(|x| x.rates.iter()))
+    }
+
+    // index as in select_flight_mode
+    fn flight_mode(&self, index: usize) -> Option<&FlightMode> {
+        index.checked_sub(1).map(|i| &self.flight_modes[i])
+    }
+
+    fn is_line_enabled(&self, line: &MixLine, flight_mode: Option<&FlightMode>) -> bool {
+        match (&line.name, flight_mode) {
+            (Some(name), Some(mode)) if mode.disable_mix.contains(name) => false,
+            (Some(name), Some(mode)) if mode.enable_mix.contains(name) => true,
+            _ => !line.disabled,
+        }
+    }
+
+    // centered value through a curve, unchanged without one
+    fn apply_curve(&self, curve: Option<&str>, value: i32) -> i32 {
+        match curve.and_then(|name| self.curves.iter().find(|x| x.name == name)) {
+            Some(curve) => curve.eval(value + FULL_SCALE) - FULL_SCALE,
+            None => value,
+        }
+    }
+
+    // calibrated input names read by the mix lines, rates and trims
+    pub fn input_names(&self) -> Vec<&str> {
+        let mode_trims = self.flight_modes.iter().flat_map(|x| x.trims.keys().map(|x| x.as_str()));
+        self.mix
+            .iter()
+            .filter_map(|line| match &line.source {
+                Source::Input(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .chain(self.all_rates().map(|x| x.input.as_str()))
+            .chain(self.trims.iter().map(|x| x.input.as_str()))
+            .chain(mode_trims)
+            .chain(self.logical_switches.iter().flat_map(|x| x.inputs()))
+            .collect()
+    }
+
+    // rates, then the trim of configured inputs. the flight mode ones come first
+    fn input_value(&self, name: &str, value: i32, inputs: &MixInputs) -> i32 {
+        let flight_mode = self.flight_mode(inputs.flight_mode);
+        let rates = flight_mode
+            .and_then(|x| x.rates.iter().find(|x| x.input.eq_ignore_ascii_case(name)))
+            .or_else(|| self.rates.iter().find(|x| x.input.eq_ignore_ascii_case(name)));
+        let value = match rates {
+            Some(rates) => rates.apply(value, inputs.switches, self),
+            None => value,
+        };
+        if let Some(trim) = flight_mode.and_then(|x| x.trim(name)) {
+            value + trim
+        } else if self.trims.iter().any(|x| x.input.eq_ignore_ascii_case(name)) {
+            value + inputs.trims.get(name)
+        } else {
+            value
+        }
+    }
+
+    // lines are applied in file order, then the output stage of the channel
+    // channels without any line stay at 0, a channel whose lines are all switched off
+    // (by their switch or a flight mode) is centered and still goes through its output stage
+    pub fn mix(&self, inputs: &MixInputs) -> [u16; MIXER_CHANNEL_NUM] {
+        let rated: Vec<(&str, i32)> = inputs
+            .inputs
+            .iter()
+            .map(|(name, value)| (*name, self.input_value(name, *value, inputs)))
+            .collect();
+        let inputs = &MixInputs {
+            inputs: &rated,
+            ..*inputs
+        };
+        let flight_mode = self.flight_mode(inputs.flight_mode);
+
+        let mut sums: [Option<i32>; MIXER_CHANNEL_NUM] = [None; MIXER_CHANNEL_NUM];
+        for line in self.mix.iter() {
+            // the channel is in use even when this line is off
+            let sum = sums[line.channel as usize - 1].get_or_insert(0);
+            if !self.is_line_enabled(line, flight_mode) || !line.switch.is_none_or(|x| x.is_active(inputs.switches)) {
+                continue;
+            }
+            let value = self.apply_curve(line.curve.as_deref(), inputs.value(&line.source)) * line.weight / 100
+                + line.offset * FULL_SCALE / 100;
+            *sum = match line.mode {
+                MixMode::Add => *sum + value,
+                MixMode::Replace => value,
+                MixMode::Multiply => *sum * value / FULL_SCALE,
+            };
+        }
+        let mut channels = [0; MIXER_CHANNEL_NUM];
+        for (i, sum) in sums.iter().enumerate() {
+            if let Some(sum) = sum {
+                channels[i] = match self.outputs.iter().find(|x| x.channel as usize == i + 1) {
+                    Some(output) => output.apply(*sum),
+                    None => OutputConfig::new(i as u8 + 1).apply(*sum),
+                };
+            }
+        }
+        channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(channel: u8, source: &str, weight: i32) -> MixLine {
+        MixLine {
+            name: None,
+            disabled: false,
+            channel,
+            source: Source::try_from(source.to_string()).unwrap(),
+            curve: None,
+            weight,
+            offset: 0,
+            mode: MixMode::Add,
+            switch: None,
+        }
+    }
+
+    // stick values in percent
+    fn mix(model: &Model, sticks: &[(&str, i32)], switches: &SwitchStateMsg) -> [u16; MIXER_CHANNEL_NUM] {
+        mix_trimmed(model, sticks, switches, &Trims::default())
+    }
+
+    fn mix_trimmed(model: &Model, sticks: &[(&str, i32)], switches: &SwitchStateMsg, trims: &Trims) -> [u16; MIXER_CHANNEL_NUM] {
+        let inputs: Vec<(&str, i32)> = sticks.iter().map(|(name, v)| (*name, v * FULL_SCALE / 100)).collect();
+        model.mix(&MixInputs {
+            inputs: &inputs,
+            switches: &Switches::from(*switches),
+            trims,
+            flight_mode: 0,
+        })
+    }
+
+    #[test]
+    fn test_default_model() {
+        let model = Model::default();
+        let out = mix(
+            &model,
+            &[("Thrust", -100), ("Direction", 0), ("Aileron", 100), ("Elevator", 50)],
+            &SwitchStateMsg::default(),
+        );
+        assert_eq!(out[..4], [10000, 7500, 0, 5000]);
+        assert!(out[4..].iter().all(|x| *x == 0));
+        assert!(model.validate().is_ok());
+
+        // weight and offset are bounded so the mix can't overflow
+        let mut invalid = model.clone();
+        invalid.mix[0].weight = 1_000_000;
+        assert!(invalid.validate().is_err());
+        let mut invalid = model.clone();
+        invalid.mix[0].offset = -501;
+        assert!(invalid.validate().is_err());
+        invalid.mix[0].offset = -500;
+        invalid.mix[0].weight = 500;
+        assert!(invalid.validate().is_ok());
+    }
+
+    #[test]
+    fn test_elevon() {
+        let model = Model {
+            mix: vec![
+                line(1, "Aileron", 50),
+                line(1, "Elevator", 50),
+                line(2, "Aileron", -50),
+                line(2, "Elevator", 50),
+            ],
+            ..Model::default()
+        };
+        let sw = SwitchStateMsg::default();
+        // pitch up moves both elevons up
+        assert_eq!(mix(&model, &[("Elevator", 100), ("Aileron", 0)], &sw)[..2], [7500, 7500]);
+        // roll moves them in opposite directions
+        assert_eq!(mix(&model, &[("Elevator", 0), ("Aileron", 100)], &sw)[..2], [7500, 2500]);
+        // full pitch and roll saturates one side
+        assert_eq!(mix(&model, &[("Elevator", 100), ("Aileron", 100)], &sw)[..2], [10000, 5000]);
+    }
+
+    #[test]
+    fn test_vtail() {
+        let model = Model {
+            mix: vec![
+                line(2, "Elevator", 50),
+                line(2, "Direction", 50),
+                line(4, "Elevator", 50),
+                line(4, "Direction", -50),
+            ],
+            ..Model::default()
+        };
+        let sw = SwitchStateMsg::default();
+        assert_eq!(mix(&model, &[("Elevator", -100), ("Direction", 0)], &sw)[1..4], [2500, 0, 2500]);
+        assert_eq!(mix(&model, &[("Elevator", 0), ("Direction", -60)], &sw)[1..4], [3500, 0, 6500]);
+        // unused channel 3 stays low
+        assert_eq!(mix(&model, &[("Elevator", 0), ("Direction", 0)], &sw)[2], 0);
+    }
+
+    #[test]
+    fn test_flaperon() {
+        // ailerons on 1 and 5, the right servo is mirrored, sw1 at position 1 lowers both as flaps
+        let flap = |channel| MixLine {
+            offset: -30,
+            switch: Some(SwitchCondition::try_from("sw1:1".to_string()).unwrap()),
+            ..line(channel, "max", 0)
+        };
+        let model = Model {
+            mix: vec![line(1, "Aileron", 100), flap(1), line(5, "Aileron", -100), flap(5)],
+            ..Model::default()
+        };
+        let mut sw = SwitchStateMsg::default();
+        let out = mix(&model, &[("Aileron", 40)], &sw);
+        assert_eq!((out[0], out[4]), (7000, 3000));
+
+        sw.value[0] = 1;
+        let out = mix(&model, &[("Aileron", 40)], &sw);
+        assert_eq!((out[0], out[4]), (5500, 1500));
+        let out = mix(&model, &[("Aileron", 0)], &sw);
+        assert_eq!((out[0], out[4]), (3500, 3500));
+    }
+
+    #[test]
+    fn test_replace_and_multiply() {
+        // throttle cut on sw2 and a throttle limit scaled by a pot
+        let model = Model {
+            mix: vec![
+                line(3, "Thrust", 100),
+                MixLine {
+                    mode: MixMode::Multiply,
+                    ..line(3, "Pot", 100)
+                },
+                MixLine {
+                    mode: MixMode::Replace,
+                    offset: -100,
+                    switch: Some(SwitchCondition::try_from("sw2:1".to_string()).unwrap()),
+                    ..line(3, "max", 0)
+                },
+            ],
+            ..Model::default()
+        };
+        let mut sw = SwitchStateMsg::default();
+        assert_eq!(mix(&model, &[("Thrust", 100), ("Pot", 100)], &sw)[2], 10000);
+        assert_eq!(mix(&model, &[("Thrust", 100), ("Pot", 50)], &sw)[2], 7500);
+        assert_eq!(mix(&model, &[("Thrust", -100), ("Pot", 50)], &sw)[2], 2500);
+        sw.value[1] = 1;
+        assert_eq!(mix(&model, &[("Thrust", 100), ("Pot", 100)], &sw)[2], 0);
+    }
+
+    #[test]
+    fn test_switched_off_channel() {
+        let mut off = line(1, "Aileron", 100);
+        off.switch = Some(SwitchCondition::try_from("sw1:1".to_string()).unwrap());
+        let model = Model {
+            mix: vec![off],
+            outputs: vec![OutputConfig {
+                subtrim: 10,
+                ..OutputConfig::new(1)
+            }],
+            ..Model::default()
+        };
+        let out = mix(&model, &[("Aileron", 100)], &SwitchStateMsg::default());
+        assert_eq!(out[..2], [5500, 0]);
+    }
+
+    #[test]
+    fn test_switch_source() {
+        let model = Model {
+            mix: vec![line(5, "sw1", 100), line(6, "SW2", 100)],
+            ..Model::default()
+        };
+        let mut sw = SwitchStateMsg::default();
+        sw.positions[0] = 2;
+        sw.positions[1] = 3;
+        assert_eq!(mix(&model, &[], &sw)[4..6], [0, 0]);
+        sw.value = [1, 1, 0, 0, 0, 0, 0, 0];
+        assert_eq!(mix(&model, &[], &sw)[4..6], [10000, 5000]);
+        sw.value[1] = 2;
+        assert_eq!(mix(&model, &[], &sw)[5], 10000);
+    }
+
+    #[test]
+    fn test_expo() {
+        assert_eq!(expo(0, 50), 0);
+        assert_eq!(expo(FULL_SCALE, 50), FULL_SCALE);
+        assert_eq!(expo(-FULL_SCALE, 100), -FULL_SCALE);
+        assert_eq!(expo(2500, 0), 2500);
+        // 0.5 * 0.5 + 0.125 * 0.5
+        assert_eq!(expo(2500, 50), 1562);
+        assert_eq!(expo(-2500, 100), -625);
+        // negative expo makes the center more sensitive
+        assert_eq!(expo(2500, -50), 3437);
+    }
+
+    #[test]
+    fn test_rates() {
+        let model = Model {
+            rates: vec![Rates {
+                input: "Aileron".to_string(),
+                switch: Some(SwitchRef(SwitchId::Physical(1))),
+                sets: vec![
+                    RateSet { rate: 100, expo: 0, curve: None },
+                    RateSet { rate: 50, expo: 0, curve: None },
+                    RateSet { rate: 80, expo: 100, curve: None },
+                ],
+            }],
+            ..Model::default()
+        };
+        assert!(model.validate().is_ok());
+        let mut sw = SwitchStateMsg::default();
+        assert_eq!(mix(&model, &[("Aileron", 100), ("Elevator", 100)], &sw)[..2], [10000, 10000]);
+        sw.value[1] = 1;
+        assert_eq!(mix(&model, &[("Aileron", 100), ("Elevator", 100)], &sw)[..2], [7500, 10000]);
+        assert_eq!(mix(&model, &[("aileron", -50)], &sw)[0], 3750);
+        sw.value[1] = 2;
+        assert_eq!(mix(&model, &[("Aileron", 50)], &sw)[0], 5500);
+        assert_eq!(mix(&model, &[("Aileron", -100)], &sw)[0], 1000);
+
+        let mut invalid = model.clone();
+        invalid.rates[0].sets[0].expo = 101;
+        assert!(invalid.validate().is_err());
+        invalid.rates[0].sets.clear();
+        assert!(invalid.validate().is_err());
+        let mut invalid = model.clone();
+        invalid.rates.push(model.rates[0].clone());
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_curves() {
+        let model = toml::from_str::<Model>(
+            r#"
+            [[mix]]
+            channel = 3
+            source = "Thrust"
+            curve = "thr_mid"
+
+            [[mix]]
+            channel = 6
+            source = "Pot"
+            curve = "pitch"
+            weight = 50
+
+            [[mix]]
+            channel = 2
+            source = "Elevator"
+
+            [[rates]]
+            input = "Elevator"
+            sets = [{ curve = "half" }]
+
+            [[curves]]
+            name = "thr_mid"
+            y = [0, 4500, 5000, 5500, 10000]
+
+            [[curves]]
+            name = "pitch"
+            y = [2000, 5000, 10000]
+            x = [0, 2000, 10000]
+            smooth = true
+
+            [[curves]]
+            name = "half"
+            y = [2500, 5000, 7500]
+            "#,
+        )
+        .unwrap();
+        assert!(model.validate().is_ok());
+        let sw = SwitchStateMsg::default();
+        let out = mix(&model, &[("Thrust", -50), ("Pot", -60), ("Elevator", 100)], &sw);
+        // x 2500 is the second point of thr_mid
+        assert_eq!(out[2], 4500);
+        // x 2000 is the center point of pitch
+        assert_eq!(out[5], 5000);
+        // the rate curve halves the input before the mix line reads it
+        assert_eq!(out[1], 7500);
+        assert_eq!(mix(&model, &[("Pot", 100)], &sw)[5], 7500);
+
+        let mut invalid = model.clone();
+        invalid.mix[0].curve = Some("missing".to_string());
+        assert!(invalid.validate().is_err());
+        let mut invalid = model.clone();
+        invalid.curves.push(model.curves[0].clone());
+        assert!(invalid.validate().is_err());
+        let mut invalid = model.clone();
+        invalid.curves[0].y.pop();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_trims() {
+        let model = toml::from_str::<Model>(
+            r#"
+            [[mix]]
+            channel = 1
+            source = "Aileron"
+            weight = 50
+
+            [[mix]]
+            channel = 2
+            source = "Elevator"
+
+            [[rates]]
+            input = "Aileron"
+            sets = [{ rate = 50 }]
+
+            [[trims]]
+            input = "Aileron"
+            up = "btn1"
+            down = "btn2"
+
+            [[trims]]
+            input = "Elevator"
+            step = 10
+            range = 500
+            "#,
+        )
+        .unwrap();
+        assert!(model.validate().is_ok());
+        assert_eq!(model.trims[0].step, 20);
+        assert_eq!(model.trims[0].up, Some(SwitchCondition { switch: SwitchId::Button(1), position: 1 }));
+
+        let sw = SwitchStateMsg::default();
+        let mut trims = Trims::default();
+        trims.step(&model.trims[0], 10);
+        trims.step(&model.trims[1], -3);
+        // trims are added after rates, the mix line weight applies to both
+        let out = mix_trimmed(&model, &[("Aileron", 100), ("Elevator", 0)], &sw, &trims);
+        assert_eq!(out[..2], [5000 + (2500 + 200) / 2, 4970]);
+
+        // saved values of inputs without a trim config are ignored
+        trims.values.insert("Thrust".to_string(), 300);
+        let model = Model { trims: Vec::new(), ..model };
+        let out = mix_trimmed(&model, &[("Aileron", 100), ("Elevator", 0)], &sw, &trims);
+        assert_eq!(out[..2], [6250, 5000]);
+
+        let mut invalid = toml::from_str::<Model>("[[trims]]\ninput = \"Aileron\"\nstep = 0").unwrap();
+        assert!(invalid.validate().is_err());
+        invalid.trims[0].step = 10;
+        assert!(invalid.validate().is_ok());
+        invalid.trims.push(invalid.trims[0].clone());
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_outputs() {
+        let model = toml::from_str::<Model>(
+            r#"
+            [[mix]]
+            channel = 1
+            source = "Aileron"
+
+            [[mix]]
+            channel = 2
+            source = "Aileron"
+
+            [[outputs]]
+            channel = 2
+            max = 50
+            subtrim = 5
+            reverse = true
+            ppm_center = 1520
+            "#,
+        )
+        .unwrap();
+        assert!(model.validate().is_ok());
+        assert_eq!(model.outputs[0].min, -100);
+        let sw = SwitchStateMsg::default();
+        assert_eq!(mix(&model, &[("Aileron", 0)], &sw)[..3], [5000, 5450, 0]);
+        assert_eq!(mix(&model, &[("Aileron", 100)], &sw)[..2], [10000, 450]);
+        // reversed: the minimum side is limited to 50%
+        assert_eq!(mix(&model, &[("Aileron", -100)], &sw)[..2], [0, 7700]);
+
+        let mut invalid = model.clone();
+        invalid.outputs.push(OutputConfig::new(2));
+        assert!(invalid.validate().is_err());
+        invalid.outputs[1].channel = 17;
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_logical_switches() {
+        let model = toml::from_str::<Model>(
+            r#"
+            [[mix]]
+            channel = 1
+            source = "ls1"
+
+            [[mix]]
+            channel = 2
+            source = "Aileron"
+            switch = "ls2"
+
+            [[rates]]
+            input = "Aileron"
+            switch = "ls1"
+            sets = [{ rate = 100 }, { rate = 50 }]
+
+            [[logical_switches]]
+            func = "compare"
+            a = "Thrust"
+            op = ">"
+
+            [[logical_switches]]
+            func = "or"
+            conditions = ["btn2", "ls1:0"]
+            "#,
+        )
+        .unwrap();
+        assert!(model.validate().is_ok());
+        assert_eq!(model.input_names(), ["Aileron", "Aileron", "Thrust"]);
+
+        let mut switches = Switches::default();
+        let inputs = [("Aileron", FULL_SCALE)];
+        let trims = Trims::default();
+        let mut mix = |logical: [bool; 2]| {
+            switches.logical[..2].copy_from_slice(&logical);
+            let out = model.mix(&MixInputs {
+                inputs: &inputs,
+                switches: &switches,
+                trims: &trims,
+                flight_mode: 0,
+            });
+            [out[0], out[1]]
+        };
+        assert_eq!(mix([false, true]), [0, 10000]);
+        assert_eq!(mix([true, true]), [10000, 7500]);
+        assert_eq!(mix([true, false]), [10000, 5000]);
+
+        assert_eq!(SwitchCondition::try_from("LS2".to_string()), Ok(SwitchCondition { switch: SwitchId::Logical(1), position: 1 }));
+        assert_eq!(SwitchCondition::try_from("btn0:0".to_string()), Ok(SwitchCondition { switch: SwitchId::Button(0), position: 0 }));
+        assert!(SwitchCondition::try_from("ls0".to_string()).is_err());
+        let mut invalid = model.clone();
+        invalid.mix[1].switch = Some(SwitchCondition::try_from("ls3".to_string()).unwrap());
+        assert!(invalid.validate().is_err());
+        invalid.mix.truncate(1);
+        invalid.logical_switches.truncate(1);
+        assert!(invalid.validate().is_ok());
+        invalid.rates[0].switch = Some(SwitchRef::try_from("ls2".to_string()).unwrap());
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_load_model() {
+        let model = toml::from_str::<Model>(
+            r#"
+            [[mix]]
+            channel = 1
+            source = "Aileron"
+            weight = -50
+            offset = 10
+            mode = "replace"
+            switch = "sw3:2"
+
+            [[mix]]
+            channel = 16
+            source = "max"
+
+            [[rates]]
+            input = "Elevator"
+            switch = "sw2"
+            sets = [{ expo = 30 }, { rate = 60, expo = 20 }]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            model.mix[0],
+            MixLine {
+                name: None,
+                disabled: false,
+                channel: 1,
+                source: Source::Input("Aileron".to_string()),
+                curve: None,
+                weight: -50,
+                offset: 10,
+                mode: MixMode::Replace,
+                switch: Some(SwitchCondition { switch: SwitchId::Physical(2), position: 2 }),
+            }
+        );
+        assert_eq!(model.mix[1].source, Source::Max);
+        assert_eq!(model.mix[1].weight, 100);
+        assert!(model.validate().is_ok());
+        assert_eq!(model.input_names(), ["Aileron", "Elevator"]);
+        assert_eq!(model.rates[0].switch, Some(SwitchRef(SwitchId::Physical(1))));
+        assert_eq!(
+            model.rates[0].sets,
+            [RateSet { rate: 100, expo: 30, curve: None }, RateSet { rate: 60, expo: 20, curve: None }]
+        );
+        assert!(toml::from_str::<Model>("[[rates]]\ninput = \"Aileron\"\nswitch = \"Aileron\"\nsets = []").is_err());
+
+        assert!(toml::from_str::<Model>("[[mix]]\nchannel = 1\nsource = \"sw9\"").is_err());
+        assert!(toml::from_str::<Model>("[[mix]]\nchannel = 1\nsource = \"max\"\nswitch = \"sw1\"").is_err());
+        let model = toml::from_str::<Model>("[[mix]]\nchannel = 17\nsource = \"max\"").unwrap();
+        assert!(model.validate().is_err());
+    }
+}
@@ -2,7 +2,10 @@ use std::sync::{Arc, LazyLock};
 
 use morb::{MorbDataType, Publisher, Subscriber, Topic};
 
-use crate::{adc::AdcRawMsg, mixer::MixerOutMsg};
+use crate::{
+    adc::AdcRawMsg, device::DeviceStatusMsg, ev_dev::ButtonMsg, flight_mode::FlightModeMsg,
+    gpio_switch::SwitchStateMsg, mixer::MixerOutMsg,
+};
 
 const LATEST_ONLY_QUEUE_SIZE: u16 = 1;
 
@@ -16,6 +19,18 @@ static ADC_RAW_TOPIC: LazyLock<Arc<Topic<AdcRawMsg>>> =
 static MIXER_OUT_TOPIC: LazyLock<Arc<Topic<MixerOutMsg>>> =
     LazyLock::new(|| create_or_get_topic("mixer_out"));
 
+static BUTTON_TOPIC: LazyLock<Arc<Topic<ButtonMsg>>> =
+    LazyLock::new(|| create_or_get_topic("button"));
+
+static SWITCH_STATE_TOPIC: LazyLock<Arc<Topic<SwitchStateMsg>>> =
+    LazyLock::new(|| create_or_get_topic("switch_state"));
+
+static FLIGHT_MODE_TOPIC: LazyLock<Arc<Topic<FlightModeMsg>>> =
+    LazyLock::new(|| create_or_get_topic("flight_mode"));
+
+static DEVICE_STATUS_TOPIC: LazyLock<Arc<Topic<DeviceStatusMsg>>> =
+    LazyLock::new(|| create_or_get_topic("device_status"));
+
 pub fn adc_raw_publisher() -> Publisher<AdcRawMsg> {
     ADC_RAW_TOPIC.create_publisher()
 }
@@ -32,6 +47,40 @@ pub fn mixer_out_subscriber() -> TopicReader<MixerOutMsg> {
     TopicReader::new(MIXER_OUT_TOPIC.clone())
 }
 
+pub fn button_publisher() -> Publisher<ButtonMsg> {
+    BUTTON_TOPIC.create_publisher()
+}
+
+pub fn button_subscriber() -> TopicReader<ButtonMsg> {
+    TopicReader::new(BUTTON_TOPIC.clone())
+}
+
+pub fn switch_state_publisher() -> Publisher<SwitchStateMsg> {
+    SWITCH_STATE_TOPIC.create_publisher()
+}
+
+pub fn switch_state_subscriber() -> TopicReader<SwitchStateMsg> {
+    TopicReader::new(SWITCH_STATE_TOPIC.clone())
+}
+
+pub fn flight_mode_publisher() -> Publisher<FlightModeMsg> {
+    FLIGHT_MODE_TOPIC.create_publisher()
+}
+
+#[allow(dead_code)]
+pub fn flight_mode_subscriber() -> TopicReader<FlightModeMsg> {
+    TopicReader::new(FLIGHT_MODE_TOPIC.clone())
+}
+
+pub fn device_status_publisher() -> Publisher<DeviceStatusMsg> {
+    DEVICE_STATUS_TOPIC.create_publisher()
+}
+
+#[allow(dead_code)]
+pub fn device_status_subscriber() -> TopicReader<DeviceStatusMsg> {
+    TopicReader::new(DEVICE_STATUS_TOPIC.clone())
+}
+
 pub struct TopicReader<T: MorbDataType> {
     subscriber: Subscriber<T>,
 }
@@ -47,7 +96,6 @@ impl<T: MorbDataType> TopicReader<T> {
         self.subscriber.read(None).unwrap()
     }
 
-    #[allow(dead_code)]
     pub fn try_read(&mut self) -> Option<T> {
         self.subscriber.check_update_and_copy()
     }
@@ -0,0 +1,140 @@
+use crate::model::{FULL_SCALE, MIXER_CHANNEL_NUM};
+
+// pulse width of the 0 ~ 10000 output scale: 1000us ~ 2000us
+pub const PPM_CENTER_US: i32 = 1500;
+const PPM_HALF_RANGE_US: i32 = 500;
+
+// model file, e.g. a servo that centers at 1520us, limited on one side
+// [[outputs]]
+// channel = 2
+// min = -80
+// subtrim = 3
+// reverse = true
+// ppm_center = 1520
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct OutputConfig {
+    // 1 ~ 16
+    pub channel: u8,
+    // percent, the output never goes past the limits
+    #[serde(default = "default_min")]
+    pub min: i32,
+    #[serde(default = "default_max")]
+    pub max: i32,
+    // percent, moves the center
+    #[serde(default)]
+    pub subtrim: i32,
+    #[serde(default)]
+    pub reverse: bool,
+    // us, the pulse width the channel centers at
+    #[serde(default = "default_ppm_center")]
+    pub ppm_center: i32,
+}
+
+fn default_min() -> i32 {
+    -100
+}
+
+fn default_max() -> i32 {
+    100
+}
+
+fn default_ppm_center() -> i32 {
+    PPM_CENTER_US
+}
+
+impl OutputConfig {
+    // unchanged output
+    pub fn new(channel: u8) -> Self {
+        OutputConfig {
+            channel,
+            min: default_min(),
+            max: default_max(),
+            subtrim: 0,
+            reverse: false,
+            ppm_center: PPM_CENTER_US,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MIXER_CHANNEL_NUM).contains(&(self.channel as usize)) {
+            return Err(format!("output channel {} should be 1 ~ {}", self.channel, MIXER_CHANNEL_NUM));
+        }
+        if !(-100..0).contains(&self.min) || !(1..=100).contains(&self.max) || !(self.min..=self.max).contains(&self.subtrim) {
+            return Err(format!(
+                "output channel {}: min {} should be -100 ~ -1, max {} should be 1 ~ 100, subtrim {} should be between them",
+                self.channel, self.min, self.max, self.subtrim
+            ));
+        }
+        let ppm_range = PPM_CENTER_US - PPM_HALF_RANGE_US / 2..=PPM_CENTER_US + PPM_HALF_RANGE_US / 2;
+        if !ppm_range.contains(&self.ppm_center) {
+            return Err(format!("output channel {}: ppm_center {} should be {:?}", self.channel, self.ppm_center, ppm_range));
+        }
+        Ok(())
+    }
+
+    // centered mix value -> 0 ~ 10000
+    // the mix is reversed, scaled into the limits and moved by subtrim, ppm_center shifts everything
+    pub fn apply(&self, value: i32) -> u16 {
+        let value = if self.reverse { -value } else { value };
+        let scaled = if value > 0 {
+            value * self.max / 100
+        } else {
+            value * -self.min / 100
+        };
+        let shift = (self.ppm_center - PPM_CENTER_US) * FULL_SCALE / PPM_HALF_RANGE_US;
+        let limited = (scaled + self.subtrim * FULL_SCALE / 100).clamp(self.min * FULL_SCALE / 100, self.max * FULL_SCALE / 100);
+        (limited + shift + FULL_SCALE).clamp(0, 2 * FULL_SCALE) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output() {
+        let out = OutputConfig::new(1);
+        assert!(out.validate().is_ok());
+        assert_eq!([-5000, 0, 2500, 5000, 6000].map(|x| out.apply(x)), [0, 5000, 7500, 10000, 10000]);
+
+        let out = OutputConfig {
+            min: -80,
+            max: 50,
+            ..OutputConfig::new(1)
+        };
+        assert_eq!([-5000, -2500, 0, 5000].map(|x| out.apply(x)), [1000, 3000, 5000, 7500]);
+
+        let out = OutputConfig {
+            reverse: true,
+            ..OutputConfig::new(1)
+        };
+        assert_eq!([-5000, 0, 1000].map(|x| out.apply(x)), [10000, 5000, 4000]);
+
+        // subtrim moves the center, the limits stay
+        let out = OutputConfig {
+            max: 90,
+            subtrim: 10,
+            ..OutputConfig::new(1)
+        };
+        assert_eq!([-5000, 0, 2500, 5000].map(|x| out.apply(x)), [500, 5500, 7750, 9500]);
+
+        // 1520us center: +20us is +200 on the 0 ~ 10000 scale, limits move with it
+        let out = OutputConfig {
+            ppm_center: 1520,
+            ..OutputConfig::new(1)
+        };
+        assert!(out.validate().is_ok());
+        assert_eq!([-5000, 0, 4000, 5000].map(|x| out.apply(x)), [200, 5200, 9200, 10000]);
+    }
+
+    #[test]
+    fn test_validate_output() {
+        assert!(OutputConfig::new(0).validate().is_err());
+        assert!(OutputConfig::new(17).validate().is_err());
+        assert!(OutputConfig { min: 0, ..OutputConfig::new(1) }.validate().is_err());
+        assert!(OutputConfig { max: 101, ..OutputConfig::new(1) }.validate().is_err());
+        assert!(OutputConfig { max: 50, subtrim: 60, ..OutputConfig::new(1) }.validate().is_err());
+        assert!(OutputConfig { ppm_center: 1000, ..OutputConfig::new(1) }.validate().is_err());
+        assert!(OutputConfig { ppm_center: 1250, ..OutputConfig::new(1) }.validate().is_ok());
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use clap::Parser;
+use rpos::thread_logln;
+
+use crate::{
+    adc::{now_us, AdcRawMsg, AdcSource, ADC_CHANNEL_NUM},
+    client_process_args,
+    mixer::MixerOutMsg,
+    model::MIXER_CHANNEL_NUM,
+    msgbus::{adc_raw_subscriber, mixer_out_subscriber},
+};
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Parser)]
+#[command(name="record", about = "record adc_raw(and mixer_out) to a csv file, `record stop` to finish", long_about = None)]
+struct Cli {
+    /// also record mixer_out.
+    #[arg(short, long)]
+    mixer_out: bool,
+
+    /// output file, or `stop` to finish the running record.
+    file: String,
+}
+
+// one line per sample:
+// adc_raw,<timestamp_us>,<source>,<value0>,<value1>,...
+//   up to the last valid channel, invalid channels before it are left empty: 1,,3
+// mixer_out,<timestamp_us>,<ch1>,<ch2>,...,<ch16>
+#[derive(Clone)]
+pub enum RecordLine {
+    AdcRaw(AdcRawMsg),
+    MixerOut(u64, MixerOutMsg),
+}
+
+impl RecordLine {
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            RecordLine::AdcRaw(msg) => msg.timestamp,
+            RecordLine::MixerOut(timestamp, _) => *timestamp,
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        match self {
+            RecordLine::AdcRaw(msg) => {
+                let len = msg.valid.iter().rposition(|x| *x).map_or(0, |i| i + 1);
+                let values: Vec<String> = msg.value[..len]
+                    .iter()
+                    .zip(msg.valid)
+                    .map(|(v, valid)| if valid { v.to_string() } else { String::new() })
+                    .collect();
+                format!("adc_raw,{},{:?},{}", msg.timestamp, msg.source, values.join(","))
+            }
+            RecordLine::MixerOut(timestamp, msg) => {
+                let values: Vec<String> = msg.channels.iter().map(|v| v.to_string()).collect();
+                format!("mixer_out,{},{}", timestamp, values.join(","))
+            }
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let err = || format!("invalid record line \"{}\"", line);
+        let fields: Vec<&str> = line.trim().split(',').collect();
+        if fields.len() < 3 {
+            return Err(err());
+        }
+        let timestamp: u64 = fields[1].parse().map_err(|_| err())?;
+        match fields[0] {
+            "adc_raw" => {
+                let source = *AdcSource::ITER
+                    .iter()
+                    .find(|s| format!("{:?}", s) == fields[2])
+                    .ok_or_else(err)?;
+                let values = &fields[3..];
+                if values.len() > ADC_CHANNEL_NUM {
+                    return Err(err());
+                }
+                let mut msg = AdcRawMsg {
+                    source,
+                    timestamp,
+                    ..Default::default()
+                };
+                for (index, v) in values.iter().enumerate().filter(|(_, v)| !v.is_empty()) {
+                    msg.value[index] = v.parse().map_err(|_| err())?;
+                    msg.valid[index] = true;
+                }
+                Ok(RecordLine::AdcRaw(msg))
+            }
+            "mixer_out" if fields.len() == MIXER_CHANNEL_NUM + 2 => {
+                let v = fields[2..]
+                    .iter()
+                    .map(|x| x.parse::<u16>())
+                    .collect::<Result<Vec<u16>, _>>()
+                    .map_err(|_| err())?;
+                Ok(RecordLine::MixerOut(
+                    timestamp,
+                    MixerOutMsg {
+                        channels: v.try_into().unwrap(),
+                    },
+                ))
+            }
+            _ => Err(err()),
+        }
+    }
+}
+
+// a failed write stops the recording
+fn write_line(writer: &Mutex<LineWriter<File>>, line: &RecordLine) {
+    let mut writer = writer.lock().unwrap();
+    if let Err(e) = writeln!(writer, "{}", line.to_line()) {
+        if RECORDING.swap(false, Ordering::SeqCst) {
+            thread_logln!("failed to write record, stopped: {}", e);
+        }
+    }
+}
+
+fn record_main(argc: u32, argv: *const &str) {
+    let ret = client_process_args::<Cli>(argc, argv);
+    if ret.is_none() {
+        return;
+    }
+
+    let args = ret.unwrap();
+    if args.file == "stop" {
+        RECORDING.store(false, Ordering::SeqCst);
+        return;
+    }
+    if RECORDING.swap(true, Ordering::SeqCst) {
+        thread_logln!("already recording, `record stop` first.");
+        return;
+    }
+
+    let file = match File::create(&args.file) {
+        Ok(f) => f,
+        Err(e) => {
+            thread_logln!("failed to create {}: {}", args.file, e);
+            RECORDING.store(false, Ordering::SeqCst);
+            return;
+        }
+    };
+    let writer = Arc::new(Mutex::new(LineWriter::new(file)));
+
+    if args.mixer_out {
+        let writer = writer.clone();
+        let mut rx = mixer_out_subscriber();
+        std::thread::spawn(move || {
+            while RECORDING.load(Ordering::SeqCst) {
+                if let Some(msg) = rx.try_read() {
+                    write_line(&writer, &RecordLine::MixerOut(now_us(), msg));
+                } else {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        });
+    }
+
+    thread_logln!("recording to {}.", args.file);
+    let mut rx = adc_raw_subscriber();
+    let mut count = 0;
+    while RECORDING.load(Ordering::SeqCst) {
+        if let Some(msg) = rx.try_read() {
+            write_line(&writer, &RecordLine::AdcRaw(msg));
+            count += 1;
+        } else {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+    thread_logln!("record finished, {} adc_raw samples.", count);
+}
+
+#[rpos::ctor::ctor]
+fn register() {
+    rpos::module::Module::register("record", record_main);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_line() {
+        let mut msg = AdcRawMsg::new(AdcSource::Ads1015, &[1, -2, 300, 4000]);
+        msg.timestamp = 123456;
+        let line = RecordLine::AdcRaw(msg).to_line();
+        assert_eq!(line, "adc_raw,123456,Ads1015,1,-2,300,4000");
+
+        match RecordLine::parse(&line).unwrap() {
+            RecordLine::AdcRaw(parsed) => {
+                assert_eq!(parsed.timestamp, 123456);
+                assert_eq!(parsed.source, AdcSource::Ads1015);
+                assert_eq!(parsed.channel_num(), 4);
+                assert_eq!(parsed.value, msg.value);
+            }
+            _ => panic!("wrong record type"),
+        }
+
+        // sparse channels, e.g. evdev axes mapped to 1 and 3
+        let mut msg = AdcRawMsg::new(AdcSource::Evdev, &[]);
+        msg.timestamp = 7;
+        msg.value[1] = -5;
+        msg.valid[1] = true;
+        msg.value[3] = 6;
+        msg.valid[3] = true;
+        let line = RecordLine::AdcRaw(msg).to_line();
+        assert_eq!(line, "adc_raw,7,Evdev,,-5,,6");
+        match RecordLine::parse(&line).unwrap() {
+            RecordLine::AdcRaw(parsed) => {
+                assert_eq!(parsed.valid, msg.valid);
+                assert_eq!(parsed.value, msg.value);
+            }
+            _ => panic!("wrong record type"),
+        }
+        let line = RecordLine::AdcRaw(AdcRawMsg::new(AdcSource::Sim, &[])).to_line();
+        assert!(line.ends_with(",Sim,"));
+        match RecordLine::parse(&line).unwrap() {
+            RecordLine::AdcRaw(parsed) => assert_eq!(parsed.channel_num(), 0),
+            _ => panic!("wrong record type"),
+        }
+
+        let line = RecordLine::MixerOut(
+            42,
+            MixerOutMsg {
+                channels: [1, 2, 3, 10000, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5000],
+            },
+        )
+        .to_line();
+        assert_eq!(line, "mixer_out,42,1,2,3,10000,0,0,0,0,0,0,0,0,0,0,0,5000");
+        let parsed = RecordLine::parse(&line).unwrap();
+        assert_eq!(parsed.timestamp(), 42);
+        assert_eq!(parsed.to_line(), line);
+    }
+
+    #[test]
+    fn test_record_line_invalid() {
+        assert!(RecordLine::parse("").is_err());
+        assert!(RecordLine::parse("adc_raw,1,NotASource,1").is_err());
+        assert!(RecordLine::parse("adc_raw,x,Sim,1").is_err());
+        assert!(RecordLine::parse("adc_raw,1,Sim,1,,x").is_err());
+        assert!(RecordLine::parse("mixer_out,1,2,3").is_err());
+        assert!(RecordLine::parse("unknown,1,2,3").is_err());
+    }
+}
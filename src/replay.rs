@@ -0,0 +1,157 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use rpos::thread_logln;
+
+use crate::{
+    adc::now_us,
+    client_process_args,
+    msgbus::{adc_raw_publisher, mixer_out_publisher},
+    record::RecordLine,
+};
+
+static REPLAYING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Parser)]
+#[command(name="replay", about = "republish a file written by `record`, `replay stop` to abort", long_about = None)]
+struct Cli {
+    /// playback speed, 2.0 plays twice as fast.
+    #[arg(short, long, default_value_t = 1.0)]
+    speed: f32,
+
+    /// also republish recorded mixer_out, don't run the mixer at the same time.
+    #[arg(short, long)]
+    mixer_out: bool,
+
+    #[arg(short, long)]
+    repeat: bool,
+
+    /// recorded file, or `stop` to abort the running replay.
+    file: String,
+}
+
+fn load_record(content: &str) -> Result<Vec<RecordLine>, String> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| RecordLine::parse(line).map_err(|e| format!("line {}: {}", index + 1, e)))
+        .collect()
+}
+
+// time to wait from the start of the replay before publishing each line, None when a wait is too long for Duration
+fn schedule(lines: &[RecordLine], speed: f32) -> Option<Vec<Duration>> {
+    let start = lines.first().map_or(0, |l| l.timestamp());
+    lines
+        .iter()
+        .map(|l| {
+            let recorded = Duration::from_micros(l.timestamp().saturating_sub(start));
+            Duration::try_from_secs_f64(recorded.as_secs_f64() / speed as f64).ok()
+        })
+        .collect()
+}
+
+fn replay_main(argc: u32, argv: *const &str) {
+    let ret = client_process_args::<Cli>(argc, argv);
+    if ret.is_none() {
+        return;
+    }
+
+    let args = ret.unwrap();
+    if args.file == "stop" {
+        REPLAYING.store(false, Ordering::SeqCst);
+        return;
+    }
+    if !(args.speed.is_finite() && args.speed > 0.0) {
+        thread_logln!("speed should be greater than 0.");
+        return;
+    }
+
+    let lines = match std::fs::read_to_string(&args.file).map_err(|e| e.to_string()).and_then(|s| load_record(&s)) {
+        Ok(l) => l,
+        Err(e) => {
+            thread_logln!("failed to load {}: {}", args.file, e);
+            return;
+        }
+    };
+    if REPLAYING.swap(true, Ordering::SeqCst) {
+        thread_logln!("already replaying, `replay stop` first.");
+        return;
+    }
+
+    let adc_raw_tx = adc_raw_publisher();
+    let mixer_out_tx = mixer_out_publisher();
+    let times = match schedule(&lines, args.speed) {
+        Some(times) => times,
+        None => {
+            thread_logln!("speed {} is too slow.", args.speed);
+            REPLAYING.store(false, Ordering::SeqCst);
+            return;
+        }
+    };
+
+    thread_logln!("replaying {} samples from {}.", lines.len(), args.file);
+    'replay: loop {
+        let start = Instant::now();
+        for (line, time) in lines.iter().zip(times.iter()) {
+            if let Some(wait) = time.checked_sub(start.elapsed()) {
+                std::thread::sleep(wait);
+            }
+            if !REPLAYING.load(Ordering::SeqCst) {
+                break 'replay;
+            }
+            match line {
+                RecordLine::AdcRaw(msg) => {
+                    let mut msg = *msg;
+                    msg.timestamp = now_us();
+                    adc_raw_tx.publish(msg);
+                }
+                RecordLine::MixerOut(_, msg) => {
+                    if args.mixer_out {
+                        mixer_out_tx.publish(msg.clone());
+                    }
+                }
+            }
+        }
+        if !args.repeat {
+            break;
+        }
+    }
+    REPLAYING.store(false, Ordering::SeqCst);
+    thread_logln!("replay finished.");
+}
+
+#[rpos::ctor::ctor]
+fn register() {
+    rpos::module::Module::register("replay", replay_main);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_schedule() {
+        let lines = load_record(
+            "adc_raw,1000000,Sim,1,2
+            mixer_out,1010000,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+
+            adc_raw,1020000,Sim,3,4
+            ",
+        )
+        .unwrap();
+        assert_eq!(lines.len(), 3);
+
+        let times = schedule(&lines, 1.0).unwrap();
+        assert_eq!(times, [Duration::ZERO, Duration::from_millis(10), Duration::from_millis(20)]);
+
+        let times = schedule(&lines, 2.0).unwrap();
+        assert_eq!(times[2], Duration::from_millis(10));
+        assert!(schedule(&lines, 1e-30).is_none());
+
+        assert!(load_record("adc_raw,1,Sim,1\nbroken").is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, Subcommand};
+use rpos::thread_logln;
+
+use crate::{
+    adc::{AdcRawMsg, AdcSource, ADC_CHANNEL_NUM},
+    client_process_args,
+    msgbus::adc_raw_publisher,
+};
+
+const SIM_MIN: i16 = 0;
+const SIM_MAX: i16 = 4095;
+const SIM_CENTER: i16 = (SIM_MAX - SIM_MIN) / 2;
+const KEY_STEP: i16 = 200;
+
+// values held by the manual mode, changed by `sim_input set` and `sim_input key`
+static MANUAL_VALUES: LazyLock<Mutex<[i16; ADC_CHANNEL_NUM]>> =
+    LazyLock::new(|| Mutex::new([SIM_CENTER; ADC_CHANNEL_NUM]));
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Parser)]
+#[command(name="sim_input", about = "publish simulated adc_raw values, for development without hardware", long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: SimCommand,
+}
+
+#[derive(Subcommand)]
+enum SimCommand {
+    /// generate waveforms, each <channel>:<sine|ramp|step>:<period_ms>:<min>:<max> or <channel>:const:<value>.
+    Wave {
+        #[arg(short, long, default_value_t = 100)]
+        rate_hz: u32,
+
+        #[arg(required = true, value_parser = parse_wave_spec)]
+        waves: Vec<WaveSpec>,
+    },
+    /// play a sequence file, each line is "<time_ms> <value0> <value1> ...".
+    Script {
+        #[arg(short, long)]
+        repeat: bool,
+
+        file: String,
+    },
+    /// publish values held in memory, changed by `set` and `key`.
+    Manual {
+        #[arg(short, long, default_value_t = 4)]
+        channels: usize,
+
+        #[arg(short, long, default_value_t = 100)]
+        rate_hz: u32,
+    },
+    /// set a channel of the manual mode.
+    Set { channel: usize, value: i16 },
+    /// move the manual mode sticks: w/s thrust, a/d direction, j/l aileron, i/k elevator, c center all.
+    Key { keys: String },
+    /// stop the running simulation.
+    Stop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Waveform {
+    Sine { period_ms: u64, min: i16, max: i16 },
+    Ramp { period_ms: u64, min: i16, max: i16 },
+    Step { period_ms: u64, min: i16, max: i16 },
+    Const(i16),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct WaveSpec {
+    channel: usize,
+    wave: Waveform,
+}
+
+impl Waveform {
+    fn eval(&self, t_ms: u64) -> i16 {
+        match *self {
+            Waveform::Sine { period_ms, min, max } => {
+                let phase = (t_ms % period_ms) as f32 / period_ms as f32;
+                let mid = (min as f32 + max as f32) / 2.0;
+                let amp = (max as f32 - min as f32) / 2.0;
+                (mid + amp * (2.0 * PI * phase).sin()).round() as i16
+            }
+            Waveform::Ramp { period_ms, min, max } => {
+                let pos = (t_ms % period_ms) as i64;
+                (min as i64 + (max as i64 - min as i64) * pos / period_ms as i64) as i16
+            }
+            Waveform::Step { period_ms, min, max } => {
+                if t_ms % period_ms < period_ms / 2 {
+                    min
+                } else {
+                    max
+                }
+            }
+            Waveform::Const(value) => value,
+        }
+    }
+}
+
+fn parse_wave_spec(s: &str) -> Result<WaveSpec, String> {
+    let parts: Vec<&str> = s.split(':').collect();
+    let channel = parts[0]
+        .parse::<usize>()
+        .map_err(|_| format!("invalid channel:{}", parts[0]))?;
+    if channel >= ADC_CHANNEL_NUM {
+        return Err(format!("channel {} out of range, only {} channels available", channel, ADC_CHANNEL_NUM));
+    }
+    let num_err = |_| format!("invalid number in {}", s);
+    let wave = match parts.get(1).copied() {
+        Some("const") if parts.len() == 3 => Waveform::Const(parts[2].parse().map_err(num_err)?),
+        Some(kind @ ("sine" | "ramp" | "step")) if parts.len() == 5 => {
+            let period_ms: u64 = parts[2].parse().map_err(|_| format!("invalid period in {}", s))?;
+            if period_ms == 0 {
+                return Err(format!("period of {} should not be 0", s));
+            }
+            let min = parts[3].parse().map_err(num_err)?;
+            let max = parts[4].parse().map_err(num_err)?;
+            match kind {
+                "sine" => Waveform::Sine { period_ms, min, max },
+                "ramp" => Waveform::Ramp { period_ms, min, max },
+                _ => Waveform::Step { period_ms, min, max },
+            }
+        }
+        _ => return Err(format!("{} should be <channel>:<sine|ramp|step>:<period_ms>:<min>:<max> or <channel>:const:<value>", s)),
+    };
+    Ok(WaveSpec { channel, wave })
+}
+
+fn parse_script(content: &str) -> Result<Vec<(u64, Vec<i16>)>, String> {
+    let mut ret: Vec<(u64, Vec<i16>)> = Vec::new();
+    for (line_num, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let err = || format!("line {}: invalid sample \"{}\"", line_num + 1, line);
+        let mut fields = line.split(|c: char| c.is_whitespace() || c == ',').filter(|x| !x.is_empty());
+        let time_ms: u64 = fields.next().ok_or_else(err)?.parse().map_err(|_| err())?;
+        let values = fields
+            .map(|x| x.parse::<i16>())
+            .collect::<Result<Vec<i16>, _>>()
+            .map_err(|_| err())?;
+        if values.is_empty() || values.len() > ADC_CHANNEL_NUM {
+            return Err(err());
+        }
+        if ret.last().is_some_and(|(t, _)| *t > time_ms) {
+            return Err(format!("line {}: time goes backwards", line_num + 1));
+        }
+        ret.push((time_ms, values));
+    }
+    Ok(ret)
+}
+
+fn apply_keys(values: &mut [i16], keys: &str) {
+    // (key, channel, direction), channels follow the Thrust/Direction/Aileron/Elevator order
+    const KEY_MAP: &[(char, usize, i16)] = &[
+        ('w', 0, 1),
+        ('s', 0, -1),
+        ('d', 1, 1),
+        ('a', 1, -1),
+        ('l', 2, 1),
+        ('j', 2, -1),
+        ('i', 3, 1),
+        ('k', 3, -1),
+    ];
+    for key in keys.chars() {
+        if key == 'c' {
+            values.iter_mut().for_each(|v| *v = SIM_CENTER);
+        } else if let Some((_, channel, dir)) = KEY_MAP.iter().find(|(k, _, _)| *k == key) {
+            let v = &mut values[*channel];
+            *v = v.saturating_add(dir * KEY_STEP).clamp(SIM_MIN, SIM_MAX);
+        }
+    }
+}
+
+fn start_running() -> bool {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        thread_logln!("sim_input is already running, stop it first.");
+        return false;
+    }
+    true
+}
+
+fn run_wave(rate_hz: u32, waves: &[WaveSpec]) {
+    let tx = adc_raw_publisher();
+    let channel_num = waves.iter().map(|w| w.channel + 1).max().unwrap();
+    let start = Instant::now();
+    let mut value = vec![SIM_CENTER; channel_num];
+    while RUNNING.load(Ordering::SeqCst) {
+        let t_ms = start.elapsed().as_millis() as u64;
+        for w in waves {
+            value[w.channel] = w.wave.eval(t_ms);
+        }
+        tx.publish(AdcRawMsg::new(AdcSource::Sim, &value));
+        std::thread::sleep(Duration::from_secs(1) / rate_hz.max(1));
+    }
+}
+
+fn run_script(samples: &[(u64, Vec<i16>)], repeat: bool) {
+    let tx = adc_raw_publisher();
+    loop {
+        let start = Instant::now();
+        for (time_ms, values) in samples {
+            let target = Duration::from_millis(*time_ms);
+            if let Some(wait) = target.checked_sub(start.elapsed()) {
+                std::thread::sleep(wait);
+            }
+            if !RUNNING.load(Ordering::SeqCst) {
+                return;
+            }
+            tx.publish(AdcRawMsg::new(AdcSource::Sim, values));
+        }
+        if !repeat {
+            return;
+        }
+    }
+}
+
+fn run_manual(channels: usize, rate_hz: u32) {
+    let tx = adc_raw_publisher();
+    while RUNNING.load(Ordering::SeqCst) {
+        let values = *MANUAL_VALUES.lock().unwrap();
+        tx.publish(AdcRawMsg::new(AdcSource::Sim, &values[..channels]));
+        std::thread::sleep(Duration::from_secs(1) / rate_hz.max(1));
+    }
+}
+
+fn sim_input_main(argc: u32, argv: *const &str) {
+    let ret = client_process_args::<Cli>(argc, argv);
+    if ret.is_none() {
+        return;
+    }
+
+    match ret.unwrap().command {
+        SimCommand::Wave { rate_hz, waves } => {
+            if start_running() {
+                thread_logln!("sim_input wave started!");
+                run_wave(rate_hz, &waves);
+                RUNNING.store(false, Ordering::SeqCst);
+            }
+        }
+        SimCommand::Script { repeat, file } => {
+            let samples = match std::fs::read_to_string(&file).map_err(|e| e.to_string()).and_then(|s| parse_script(&s)) {
+                Ok(s) => s,
+                Err(e) => {
+                    thread_logln!("failed to load {}: {}", file, e);
+                    return;
+                }
+            };
+            if start_running() {
+                thread_logln!("sim_input script started, {} samples.", samples.len());
+                run_script(&samples, repeat);
+                RUNNING.store(false, Ordering::SeqCst);
+                thread_logln!("sim_input script finished.");
+            }
+        }
+        SimCommand::Manual { channels, rate_hz } => {
+            if channels == 0 || channels > ADC_CHANNEL_NUM {
+                thread_logln!("channels should be in 1~{}.", ADC_CHANNEL_NUM);
+                return;
+            }
+            if start_running() {
+                thread_logln!("sim_input manual started!");
+                run_manual(channels, rate_hz);
+                RUNNING.store(false, Ordering::SeqCst);
+            }
+        }
+        SimCommand::Set { channel, value } => {
+            if channel >= ADC_CHANNEL_NUM {
+                thread_logln!("channel {} out of range.", channel);
+                return;
+            }
+            if !(SIM_MIN..=SIM_MAX).contains(&value) {
+                thread_logln!("value {} clamped to {}~{}.", value, SIM_MIN, SIM_MAX);
+            }
+            MANUAL_VALUES.lock().unwrap()[channel] = value.clamp(SIM_MIN, SIM_MAX);
+        }
+        SimCommand::Key { keys } => {
+            let mut values = MANUAL_VALUES.lock().unwrap();
+            apply_keys(&mut values[..], &keys);
+            thread_logln!("{:?}", &values[..4]);
+        }
+        SimCommand::Stop => {
+            RUNNING.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+#[rpos::ctor::ctor]
+fn register() {
+    rpos::module::Module::register("sim_input", sim_input_main);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_waveform() {
+        let sine = Waveform::Sine { period_ms: 1000, min: 0, max: 2000 };
+        assert_eq!(sine.eval(0), 1000);
+        assert_eq!(sine.eval(250), 2000);
+        assert_eq!(sine.eval(750), 0);
+        assert_eq!(sine.eval(1250), 2000);
+
+        let ramp = Waveform::Ramp { period_ms: 100, min: 100, max: 200 };
+        assert_eq!(ramp.eval(0), 100);
+        assert_eq!(ramp.eval(50), 150);
+        assert_eq!(ramp.eval(150), 150);
+
+        let step = Waveform::Step { period_ms: 100, min: -5, max: 5 };
+        assert_eq!(step.eval(10), -5);
+        assert_eq!(step.eval(60), 5);
+
+        assert_eq!(Waveform::Const(42).eval(12345), 42);
+    }
+
+    #[test]
+    fn test_parse_wave_spec() {
+        assert_eq!(
+            parse_wave_spec("1:sine:2000:0:4095").unwrap(),
+            WaveSpec { channel: 1, wave: Waveform::Sine { period_ms: 2000, min: 0, max: 4095 } }
+        );
+        assert_eq!(
+            parse_wave_spec("3:const:100").unwrap(),
+            WaveSpec { channel: 3, wave: Waveform::Const(100) }
+        );
+        assert!(parse_wave_spec("1:sine:0:0:4095").is_err());
+        assert!(parse_wave_spec("1:sine:100").is_err());
+        assert!(parse_wave_spec("99:const:100").is_err());
+        assert!(parse_wave_spec("1:square:100:0:1").is_err());
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let samples = parse_script(
+            "# time value...
+            0 100 200
+            20, 150, 250
+
+            40 200 300 400",
+        )
+        .unwrap();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[1], (20, vec![150, 250]));
+        assert_eq!(samples[2].1.len(), 3);
+
+        assert!(parse_script("0 abc").is_err());
+        assert!(parse_script("10").is_err());
+        assert!(parse_script(",").is_err());
+        assert!(parse_script(" , ,").is_err());
+        assert!(parse_script("10 1\n5 1").is_err());
+    }
+
+    #[test]
+    fn test_apply_keys() {
+        let mut values = [SIM_CENTER; 4];
+        apply_keys(&mut values, "wwaj");
+        assert_eq!(values, [SIM_CENTER + 2 * KEY_STEP, SIM_CENTER - KEY_STEP, SIM_CENTER - KEY_STEP, SIM_CENTER]);
+
+        apply_keys(&mut values, &"w".repeat(100));
+        assert_eq!(values[0], SIM_MAX);
+
+        // out of range values don't overflow
+        values[0] = i16::MAX;
+        values[1] = i16::MIN;
+        apply_keys(&mut values, "wa");
+        assert_eq!(values[..2], [SIM_MAX, SIM_MIN]);
+
+        apply_keys(&mut values, "c");
+        assert_eq!(values, [SIM_CENTER; 4]);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::calibrate::STICK_INPUTS;
+
+// physical gimbal axes of a two stick transmitter
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GimbalAxis {
+    LeftHorizontal,
+    LeftVertical,
+    RightHorizontal,
+    RightVertical,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+pub enum StickMode {
+    #[value(name = "1")]
+    #[serde(rename = "1")]
+    Mode1,
+    #[default]
+    #[value(name = "2")]
+    #[serde(rename = "2")]
+    Mode2,
+    #[value(name = "3")]
+    #[serde(rename = "3")]
+    Mode3,
+    #[value(name = "4")]
+    #[serde(rename = "4")]
+    Mode4,
+}
+
+impl StickMode {
+    pub const ITER: &'static [Self] = &[Self::Mode1, Self::Mode2, Self::Mode3, Self::Mode4];
+
+    // gimbal axes of the stick functions, in the order of STICK_INPUTS: Thrust, Direction, Aileron, Elevator
+    fn axes(self) -> [GimbalAxis; 4] {
+        use GimbalAxis::*;
+        match self {
+            StickMode::Mode1 => [RightVertical, LeftHorizontal, RightHorizontal, LeftVertical],
+            StickMode::Mode2 => [LeftVertical, LeftHorizontal, RightHorizontal, RightVertical],
+            StickMode::Mode3 => [RightVertical, RightHorizontal, LeftHorizontal, LeftVertical],
+            StickMode::Mode4 => [LeftVertical, RightHorizontal, LeftHorizontal, RightVertical],
+        }
+    }
+
+    pub fn number(self) -> u8 {
+        StickMode::ITER.iter().position(|x| *x == self).unwrap() as u8 + 1
+    }
+
+    pub fn from_number(number: u8) -> Option<Self> {
+        StickMode::ITER.get((number as usize).checked_sub(1)?).copied()
+    }
+
+    // the calibrated input, named after the stick functions of `calibrated` mode, that drives `function` in this mode
+    pub fn remap(self, function: &str, calibrated: StickMode) -> &'static str {
+        let index = STICK_INPUTS.iter().position(|(name, _)| *name == function).unwrap();
+        let axis = self.axes()[index];
+        let calibrated_index = calibrated.axes().iter().position(|x| *x == axis).unwrap();
+        STICK_INPUTS[calibrated_index].0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remap() {
+        for mode in StickMode::ITER {
+            assert_eq!(StickMode::from_number(mode.number()), Some(*mode));
+            for (name, _) in STICK_INPUTS {
+                assert_eq!(mode.remap(name, *mode), *name);
+            }
+        }
+        assert_eq!(StickMode::from_number(0), None);
+        assert_eq!(StickMode::from_number(5), None);
+
+        // calibrated in mode 2, flown in mode 1: throttle moves to the right vertical axis
+        assert_eq!(StickMode::Mode1.remap("Thrust", StickMode::Mode2), "Elevator");
+        assert_eq!(StickMode::Mode1.remap("Elevator", StickMode::Mode2), "Thrust");
+        assert_eq!(StickMode::Mode1.remap("Aileron", StickMode::Mode2), "Aileron");
+        assert_eq!(StickMode::Mode1.remap("Direction", StickMode::Mode2), "Direction");
+
+        // mode 3 swaps aileron and rudder of mode 1
+        assert_eq!(StickMode::Mode3.remap("Direction", StickMode::Mode1), "Aileron");
+        assert_eq!(StickMode::Mode3.remap("Thrust", StickMode::Mode1), "Thrust");
+
+        assert_eq!(StickMode::Mode4.remap("Aileron", StickMode::Mode2), "Direction");
+        assert_eq!(StickMode::Mode4.remap("Thrust", StickMode::Mode2), "Thrust");
+    }
+}
@@ -0,0 +1,196 @@
+use std::{collections::BTreeMap, io::ErrorKind, path::Path};
+
+use crate::{
+    model::{SwitchCondition, Switches},
+    write_file_atomic,
+};
+
+// model file, e.g.
+// [[trims]]
+// input = "Elevator"
+// step = 20
+// range = 1250
+// up = "btn4"
+// down = "btn5"
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+pub struct TrimConfig {
+    pub input: String,
+    // on the centered -5000 ~ 5000 input scale, added to the input after its rates and before the mix weights
+    #[serde(default = "default_step")]
+    pub step: i32,
+    // the trim stays within -range ~ range
+    #[serde(default = "default_range")]
+    pub range: i32,
+    // switch conditions like a mix line switch, "btn<n>" is active while the button is pressed
+    #[serde(default)]
+    pub up: Option<SwitchCondition>,
+    #[serde(default)]
+    pub down: Option<SwitchCondition>,
+}
+
+fn default_step() -> i32 {
+    20
+}
+
+fn default_range() -> i32 {
+    1250
+}
+
+// trim values by input name, saved next to the model as <model>.trim.toml
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Trims {
+    #[serde(default)]
+    pub values: BTreeMap<String, i32>,
+}
+
+pub fn trim_filename(model_filename: &str) -> String {
+    Path::new(model_filename).with_extension("trim.toml").to_string_lossy().to_string()
+}
+
+impl Trims {
+    // no file yet means no trims
+    pub fn load(filename: &str) -> Result<Self, String> {
+        match std::fs::read_to_string(filename) {
+            Ok(s) => toml::from_str::<Trims>(&s).map_err(|e| format!("{}: {}", filename, e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Trims::default()),
+            Err(e) => Err(format!("{}: {}", filename, e)),
+        }
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        write_file_atomic(filename, &toml::to_string(self).unwrap()).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    pub fn get(&self, input: &str) -> i32 {
+        self.values
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(input))
+            .map_or(0, |(_, v)| *v)
+    }
+
+    // moves the trim by `steps` steps, returns the new value
+    pub fn step(&mut self, config: &TrimConfig, steps: i32) -> i32 {
+        let value = (self.get(&config.input) + steps * config.step).clamp(-config.range, config.range);
+        self.values.retain(|name, _| !name.eq_ignore_ascii_case(&config.input));
+        self.values.insert(config.input.clone(), value);
+        value
+    }
+
+    pub fn reset(&mut self, input: &str) {
+        self.values.retain(|name, _| !name.eq_ignore_ascii_case(input));
+    }
+}
+
+// steps the trims once per press of their up/down triggers
+pub struct TrimButtons {
+    // (up, down) of each config in the last update
+    pressed: Vec<(bool, bool)>,
+}
+
+impl TrimButtons {
+    pub fn new(configs: &[TrimConfig]) -> Self {
+        TrimButtons {
+            pressed: vec![(false, false); configs.len()],
+        }
+    }
+
+    // returns true when a trim changed
+    pub fn update(&mut self, configs: &[TrimConfig], trims: &mut Trims, switches: &Switches) -> bool {
+        let mut changed = false;
+        for (config, last) in configs.iter().zip(self.pressed.iter_mut()) {
+            let active = |trigger: &Option<SwitchCondition>| trigger.is_some_and(|x| x.is_active(switches));
+            let pressed = (active(&config.up), active(&config.down));
+            if pressed.0 && !last.0 {
+                trims.step(config, 1);
+                changed = true;
+            }
+            if pressed.1 && !last.1 {
+                trims.step(config, -1);
+                changed = true;
+            }
+            *last = pressed;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::SwitchId;
+
+    fn config() -> TrimConfig {
+        TrimConfig {
+            input: "Elevator".to_string(),
+            step: 20,
+            range: 50,
+            up: Some(SwitchCondition::try_from("btn4".to_string()).unwrap()),
+            down: Some(SwitchCondition::try_from("sw2:2".to_string()).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_trim_step() {
+        let config = config();
+        let mut trims = Trims::default();
+        assert_eq!(trims.step(&config, 1), 20);
+        assert_eq!(trims.step(&config, 1), 40);
+        assert_eq!(trims.step(&config, 1), 50);
+        assert_eq!(trims.step(&config, -10), -50);
+        assert_eq!(trims.get("elevator"), -50);
+        trims.reset("ELEVATOR");
+        assert_eq!(trims.get("Elevator"), 0);
+        assert!(trims.values.is_empty());
+    }
+
+    #[test]
+    fn test_trim_buttons() {
+        let configs = [config()];
+        let mut trims = Trims::default();
+        let mut buttons = TrimButtons::new(&configs);
+        let mut sw = Switches::default();
+
+        assert!(!buttons.update(&configs, &mut trims, &sw));
+        sw.buttons.value = 1 << 4;
+        assert!(buttons.update(&configs, &mut trims, &sw));
+        // held, no repeat
+        assert!(!buttons.update(&configs, &mut trims, &sw));
+        assert_eq!(trims.get("Elevator"), 20);
+        sw.buttons.value = 0;
+        buttons.update(&configs, &mut trims, &sw);
+        sw.buttons.value = 1 << 4;
+        buttons.update(&configs, &mut trims, &sw);
+        assert_eq!(trims.get("Elevator"), 40);
+
+        sw.physical.value[1] = 2;
+        assert!(buttons.update(&configs, &mut trims, &sw));
+        assert_eq!(trims.get("Elevator"), 20);
+    }
+
+    #[test]
+    fn test_trim_trigger() {
+        let config = |up: &str| toml::from_str::<TrimConfig>(&format!("input = \"Elevator\"\nup = \"{}\"", up));
+        assert_eq!(config("btn0").unwrap().up.map(|x| x.switch), Some(SwitchId::Button(0)));
+        assert_eq!(config("ls2:0").unwrap().up.map(|x| x.position), Some(0));
+        assert!(config("btn32").is_err());
+        assert!(config("sw1").is_err());
+        assert!(config("foo").is_err());
+    }
+
+    #[test]
+    fn test_trims_persistence() {
+        assert_eq!(trim_filename("model.toml"), "model.trim.toml");
+        assert_eq!(trim_filename("/models/heli.toml"), "/models/heli.trim.toml");
+
+        let filename = std::env::temp_dir().join("test_trims_persistence.trim.toml");
+        let filename = filename.to_str().unwrap();
+        _ = std::fs::remove_file(filename);
+        assert_eq!(Trims::load(filename), Ok(Trims::default()));
+
+        let mut trims = Trims::default();
+        trims.step(&config(), -2);
+        trims.save(filename).unwrap();
+        assert_eq!(Trims::load(filename).unwrap().get("Elevator"), -40);
+        _ = std::fs::remove_file(filename);
+    }
+}
@@ -1,3 +1,3 @@
-./LinTx -- joy_dev /dev/input/js0 &
+./LinTx -- joy_dev -p steamdeck /dev/input/js0 &
 ./LinTx -- mixer &
 ./LinTx -- elrs_tx /dev/ttyUSB0 &
\ No newline at end of file